@@ -0,0 +1,60 @@
+//! Times programs through the plain interpreter and through the decode cache. Run with
+//! `cargo bench --bench decode_cache`.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use aoc::intcode::{assemble, IntcodeVM, PagedMemory};
+
+fn run(program: &PagedMemory, feed: i64, cached: bool) -> Vec<i64> {
+    let mut vm = IntcodeVM::new(program.clone());
+    vm.set_decode_cache(cached);
+    vm.run_with_inputs(vec![feed, 0]).unwrap()
+}
+
+fn decode_cache(c: &mut Criterion) {
+    let programs = [
+        // A tight loop in a subroutine, where every instruction runs thousands of times
+        (
+            "triangle",
+            assemble(
+                "triangle.asm",
+                include_str!("../src/intcode/testdata/triangle.asm"),
+            )
+            .unwrap(),
+            10_000,
+        ),
+        // A loop that rewrites one of its own instructions every time round, so the cache
+        // decodes it afresh each time
+        (
+            "self-modifying",
+            vec![
+                3, 100, 1001, 100, -1, 100, 101, 1, 11, 11, 1101, 0, 0, 50, 1005, 100, 2, 4, 50, 99,
+            ],
+            10_000,
+        ),
+        // Straight-line code that runs once, where the cache can only cost time
+        (
+            "straight-line",
+            (0..2_000)
+                .flat_map(|_| vec![1101, 1, 2, 8_001])
+                .chain(vec![99])
+                .collect(),
+            0,
+        ),
+    ];
+
+    let mut group = c.benchmark_group("decode_cache");
+    for (name, program, feed) in programs.iter() {
+        let program = PagedMemory::from(program.as_slice());
+        assert_eq!(run(&program, *feed, false), run(&program, *feed, true));
+        group.bench_with_input(BenchmarkId::new("interpreter", name), &program, |b, p| {
+            b.iter(|| run(p, *feed, false))
+        });
+        group.bench_with_input(BenchmarkId::new("cached", name), &program, |b, p| {
+            b.iter(|| run(p, *feed, true))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, decode_cache);
+criterion_main!(benches);
@@ -5,10 +5,13 @@ use proc_macro::TokenStream;
 use proc_macro2::Span;
 use quote::quote;
 use syn::parse::{Parse, ParseStream, Peek};
-use syn::{parse_macro_input, Ident, LitInt, Result, Token};
+use syn::{parse_macro_input, DeriveInput, Ident, LitInt, LitStr, Result, Token};
+
+mod parse_line;
 
 struct IntcodeOpInvocation {
     base: i64,
+    mnemonic: LitStr,
     op: Ident,
     in_args: usize,
     out_args: usize,
@@ -16,7 +19,6 @@ struct IntcodeOpInvocation {
 
 struct MultiIntcodeOpInvocations {
     ops: Vec<IntcodeOpInvocation>,
-    instruction: Ident,
 }
 
 fn must_parse<L: Peek, T: Parse>(token: L, stream: &ParseStream) -> Result<T> {
@@ -30,19 +32,11 @@ fn must_parse<L: Peek, T: Parse>(token: L, stream: &ParseStream) -> Result<T> {
 
 impl Parse for MultiIntcodeOpInvocations {
     fn parse(input: ParseStream) -> Result<Self> {
-        let n: LitInt = must_parse(LitInt, &input)?;
-        let n_n = n.base10_parse::<usize>()?;
-        let _: Token!(,) = must_parse(Token!(,), &input)?;
-        let instruction_ident = must_parse(Ident, &input)?;
-        let _: Token!(,) = must_parse(Token!(,), &input)?;
-        let mut r = MultiIntcodeOpInvocations {
-            ops: Vec::new(),
-            instruction: instruction_ident,
-        };
+        let mut r = MultiIntcodeOpInvocations { ops: Vec::new() };
 
-        for i in 0..n_n {
+        while !input.is_empty() {
             r.ops.push(IntcodeOpInvocation::parse(&input)?);
-            if i != n_n - 1 {
+            if !input.is_empty() {
                 let _: Token!(,) = must_parse(Token!(,), &input)?;
             }
         }
@@ -54,6 +48,8 @@ impl Parse for IntcodeOpInvocation {
     fn parse(input: ParseStream) -> Result<Self> {
         let base: LitInt = must_parse(LitInt, &input)?;
         let _: Token!(,) = must_parse(Token!(,), &input)?;
+        let mnemonic = must_parse(LitStr, &input)?;
+        let _: Token!(,) = must_parse(Token!(,), &input)?;
         let op_ident = must_parse(Ident, &input)?;
         let _: Token!(,) = must_parse(Token!(,), &input)?;
         let in_args: LitInt = must_parse(LitInt, &input)?;
@@ -65,6 +61,7 @@ impl Parse for IntcodeOpInvocation {
 
         Ok(IntcodeOpInvocation {
             base: base_i,
+            mnemonic,
             op: op_ident,
             in_args: in_arg_count,
             out_args: out_arg_count,
@@ -90,23 +87,50 @@ fn things<T: Clone>(a: &Vec<T>, l: usize) -> Vec<Vec<T>> {
     }
 }
 
+/// Takes the opcode table as `code, "mnemonic", handler, inputs, outputs` entries and generates
+/// both `OPCODES`, the table as data, and `IntcodeVM::execute`, which dispatches an instruction
+/// word to its handler with every parameter mode combination expanded into its own match arm.
+/// `IntcodeVM::decode_op` is generated from the same arms for the decode cache: it reads an
+/// instruction's operands once and pairs them with a handler specialised to its modes. Opcodes
+/// added to a VM at run time fall through to `IntcodeVM::execute_custom`, which has an arm for
+/// every arity and mode combination in the same way. The generated code is generic over the
+/// memory's word type, with the opcode taken as an i64.
 #[proc_macro]
-pub fn intcode_op(input: TokenStream) -> TokenStream {
+pub fn intcode_ops(input: TokenStream) -> TokenStream {
     let all_inputs = parse_macro_input!(input as MultiIntcodeOpInvocations);
 
+    let max_operands = all_inputs
+        .ops
+        .iter()
+        .map(|op| op.in_args + op.out_args)
+        .max()
+        .unwrap_or(0);
+
+    let in_opts = vec![
+        (0, quote! {PositionInput}),
+        (1, quote! {ImmediateInput}),
+        (2, quote! {RelativeInput}),
+    ];
+    let out_opts = vec![(0, quote! {PositionOutput}), (2, quote! {RelativeOutput})];
+
     let mut entries = vec![];
+    let mut decoders = vec![];
+    let mut table = vec![];
     for input in all_inputs.ops {
         let base = input.base;
         let op = input.op;
         let in_args = input.in_args;
         let out_args = input.out_args;
 
-        let in_opts = vec![
-            (0, quote! {PositionInput}),
-            (1, quote! {ImmediateInput}),
-            (2, quote! {RelativeInput}),
-        ];
-        let out_opts = vec![(0, quote! {PositionOutput}), (2, quote! {RelativeOutput})];
+        let mnemonic = input.mnemonic;
+        table.push(quote! {
+            OpcodeInfo {
+                code: #base,
+                mnemonic: #mnemonic,
+                inputs: #in_args,
+                outputs: #out_args,
+            }
+        });
 
         let arguments: Vec<Ident> = (0..in_args + out_args)
             .map(|i| Ident::new(&format!("arg{}", i), Span::call_site()))
@@ -131,44 +155,167 @@ pub fn intcode_op(input: TokenStream) -> TokenStream {
                     .chain(output_combo.iter())
                     .map(|(_, arg_type)| arg_type);
 
+                let args = in_args + out_args;
                 let assign_list: Vec<proc_macro2::TokenStream> = arguments
                     .iter()
-                    .enumerate()
                     .zip(arg_types)
-                    .map(|((i, arg), t)| {
-                        quote! {
-                            let #arg = #t::from(*unsafe{self.data.get_unchecked(self.ip + 1 + #i)})
-                        }
-                    })
+                    .map(|(arg, t)| quote! { let #arg = #t::from(#arg) })
                     .collect();
 
-                let args = in_args + out_args;
                 let entry = quote! {
                     #code => {
-                        if #args > 0 && self.data.len() < self.ip + 1 + #args {
-                            Err(IntcodeError::OutOfBoundsArguments((self.ip + 1 + #args) as i64))
-                        } else {
-                            #(#assign_list);*;
-                            let step_result = #op(self, #(#arguments),*)?;
-                            if step_result == StepResult::Continue {
-                                self.ip += 1 + #args;
-                            }
-                            Ok(step_result)
+                        let [#(#arguments),*] = match self.data.load_run::<#args>(self.ip + 1) {
+                            Some(operands) => operands,
+                            None => return Err(IntcodeErrorKind::OutOfBoundsArguments((self.ip + 1 + #args) as i64)),
+                        };
+                        #(#assign_list;)*
+                        let step_result = #op(self, #(#arguments),*)?;
+                        if step_result == StepResult::Continue {
+                            self.ip += 1 + #args;
                         }
+                        Ok(step_result)
                     }
                 };
 
                 entries.push(entry);
+
+                // The same arm again, split into reading the operands once up front and a
+                // handler that runs on operands it's handed
+                let arg_types = input_combo
+                    .iter()
+                    .chain(output_combo.iter())
+                    .map(|(_, arg_type)| arg_type);
+                let unpack_list: Vec<proc_macro2::TokenStream> = arguments
+                    .iter()
+                    .enumerate()
+                    .zip(arg_types)
+                    .map(|((i, arg), t)| quote! { let #arg = #t::from(operands[#i].clone()) })
+                    .collect();
+                let loads: Vec<proc_macro2::TokenStream> = (0..max_operands)
+                    .map(|i| {
+                        if i < args {
+                            quote! { self.data.load(self.ip + 1 + #i)? }
+                        } else {
+                            quote! { D::Word::default() }
+                        }
+                    })
+                    .collect();
+                let len = 1 + args;
+                decoders.push(quote! {
+                    #code => Some(DecodedOp {
+                        run: |vm: &mut IntcodeVM<D>, operands: &[D::Word; MAX_OPERANDS]| {
+                            #(#unpack_list;)*
+                            let step_result = #op(vm, #(#arguments),*)?;
+                            if step_result == StepResult::Continue {
+                                vm.ip += #len;
+                            }
+                            Ok(step_result)
+                        },
+                        operands: [#(#loads),*],
+                        len: #len,
+                    })
+                });
             }
         }
     }
 
-    let instruction = all_inputs.instruction;
+    // Opcodes added at run time can have any arity up to the widest built in one, so there's an
+    // arm for every mode combination of every arity, handing the handler its inputs' values and
+    // storing the values it gives back
+    let mut custom_entries = vec![];
+    for args in 0..=max_operands {
+        for in_args in 0..=args {
+            let out_args = args - in_args;
+            let arguments: Vec<Ident> = (0..args)
+                .map(|i| Ident::new(&format!("arg{}", i), Span::call_site()))
+                .collect();
+            for input_combo in things(&in_opts, in_args) {
+                for output_combo in things(&out_opts, out_args) {
+                    let mut modes = 0;
+                    let mut pow = 1;
+                    for (id, _) in input_combo.iter().chain(output_combo.iter()) {
+                        modes += id * pow;
+                        pow *= 10;
+                    }
+
+                    let arg_types = input_combo
+                        .iter()
+                        .chain(output_combo.iter())
+                        .map(|(_, arg_type)| arg_type);
+                    let assign_list: Vec<proc_macro2::TokenStream> = arguments
+                        .iter()
+                        .zip(arg_types)
+                        .map(|(arg, t)| quote! { let #arg = #t::from(#arg) })
+                        .collect();
+                    let inputs = &arguments[..in_args];
+                    let stores: Vec<proc_macro2::TokenStream> = arguments[in_args..]
+                        .iter()
+                        .enumerate()
+                        .map(|(i, arg)| quote! { *#arg.write(self)? = outputs[#i].clone() })
+                        .collect();
+
+                    custom_entries.push(quote! {
+                        (#in_args, #out_args, #modes) => {
+                            let [#(#arguments),*] = match self.data.load_run::<#args>(self.ip + 1) {
+                                Some(operands) => operands,
+                                None => return Err(IntcodeErrorKind::OutOfBoundsArguments((self.ip + 1 + #args) as i64)),
+                            };
+                            #(#assign_list;)*
+                            let inputs: [D::Word; #in_args] = [#(#inputs.read(self)?),*];
+                            let mut outputs: [D::Word; #out_args] = Default::default();
+                            let step_result = handler(self, &inputs, &mut outputs)?;
+                            #(#stores;)*
+                            if step_result != StepResult::Jump {
+                                self.ip += 1 + #args;
+                            }
+                            Ok(step_result)
+                        }
+                    });
+                }
+            }
+        }
+    }
 
     TokenStream::from(quote! {
-        match *#instruction {
-            #(#entries),*
-            _ => Err(IntcodeError::IllegalOpcode(*#instruction)),
+        pub const OPCODES: &[OpcodeInfo] = &[#(#table),*];
+
+        const MAX_OPERANDS: usize = #max_operands;
+
+        impl<D: IntcodeMemory> IntcodeVM<D> {
+            fn execute(&mut self, instruction: i64) -> Result<StepResult, IntcodeErrorKind> {
+                match instruction {
+                    #(#entries),*
+                    _ => self.execute_custom(instruction),
+                }
+            }
+
+            fn execute_custom(&mut self, instruction: i64) -> Result<StepResult, IntcodeErrorKind> {
+                let (info, handler) = match self.opcodes.iter().find(|(info, _)| info.code == instruction % 100) {
+                    Some(&opcode) => opcode,
+                    None => return Err(IntcodeErrorKind::IllegalOpcode(instruction)),
+                };
+                match (info.inputs, info.outputs, instruction / 100) {
+                    #(#custom_entries),*
+                    _ => Err(IntcodeErrorKind::IllegalOpcode(instruction)),
+                }
+            }
+
+            fn decode_op(&self, instruction: i64) -> Option<DecodedOp<D>> {
+                match instruction {
+                    #(#decoders),*,
+                    _ => None,
+                }
+            }
         }
     })
 }
+
+/// Derives `FromStr` from a `#[pattern("{min}-{max} {ch}: {password}")]` attribute. Each `{field}`
+/// takes the text up to the literal that follows it and parses it with the field's own `FromStr`.
+/// Tuple fields are named by position (`{0}`). On enums every variant carries its own pattern and
+/// the first one to match the whole line wins.
+#[proc_macro_derive(ParseLine, attributes(pattern))]
+pub fn derive_parse_line(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    TokenStream::from(parse_line::derive(input).unwrap_or_else(|e| e.to_compile_error()))
+}
@@ -0,0 +1,349 @@
+use proc_macro2::{Span, TokenStream};
+use quote::quote;
+use syn::{Attribute, Data, DeriveInput, Error, Fields, Ident, LitStr, Result, Type};
+
+enum Segment {
+    Literal(String),
+    Field(String),
+}
+
+// Splits a pattern like "{min}-{max} {ch}: {password}" into alternating literal and field
+// segments. "{{" and "}}" stand for literal braces.
+fn split_pattern(pattern: &LitStr) -> Result<Vec<Segment>> {
+    let text = pattern.value();
+    let mut segments = Vec::new();
+    let mut literal = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(Error::new(pattern.span(), "unclosed '{' in pattern")),
+                    }
+                }
+                if let Some(Segment::Field(prev)) = segments.last() {
+                    if literal.is_empty() {
+                        return Err(Error::new(
+                            pattern.span(),
+                            format!(
+                                "fields {{{}}} and {{{}}} need literal text between them",
+                                prev, name
+                            ),
+                        ));
+                    }
+                }
+                if !literal.is_empty() {
+                    segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                }
+                segments.push(Segment::Field(name.trim().to_owned()));
+            }
+            '}' => return Err(Error::new(pattern.span(), "unmatched '}' in pattern")),
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        segments.push(Segment::Literal(literal));
+    }
+    Ok(segments)
+}
+
+fn find_pattern(attrs: &[Attribute], span: Span) -> Result<LitStr> {
+    let mut found = attrs.iter().filter(|a| a.path.is_ident("pattern"));
+    match (found.next(), found.next()) {
+        (Some(attr), None) => attr.parse_args::<LitStr>(),
+        (Some(_), Some(extra)) => Err(Error::new_spanned(extra, "duplicate #[pattern] attribute")),
+        (None, _) => Err(Error::new(span, "missing #[pattern(\"...\")] attribute")),
+    }
+}
+
+// Generates the body of a parser for one pattern. It binds each field to a local, then hands the
+// locals to `construct`, which builds the value in whatever shape the struct or variant has.
+fn pattern_parser<F>(
+    owner: &str,
+    pattern: &LitStr,
+    fields: &Fields,
+    construct: F,
+) -> Result<TokenStream>
+where
+    F: FnOnce(&[Ident]) -> TokenStream,
+{
+    let members: Vec<(String, &Type)> = fields
+        .iter()
+        .enumerate()
+        .map(|(i, f)| match &f.ident {
+            Some(ident) => (ident.to_string(), &f.ty),
+            None => (i.to_string(), &f.ty),
+        })
+        .collect();
+    let locals: Vec<Ident> = members
+        .iter()
+        .map(|(name, _)| Ident::new(&format!("field_{}", name), Span::call_site()))
+        .collect();
+
+    let segments = split_pattern(pattern)?;
+    let mut seen = vec![false; members.len()];
+    let mut steps = Vec::new();
+    for (i, segment) in segments.iter().enumerate() {
+        match segment {
+            Segment::Literal(text) => steps.push(quote! { s.literal(#text)?; }),
+            Segment::Field(name) => {
+                let index = match members.iter().position(|(n, _)| n == name) {
+                    Some(index) => index,
+                    None => {
+                        return Err(Error::new(
+                            pattern.span(),
+                            format!("pattern refers to unknown field {{{}}}", name),
+                        ))
+                    }
+                };
+                if seen[index] {
+                    return Err(Error::new(
+                        pattern.span(),
+                        format!("field {{{}}} appears more than once", name),
+                    ));
+                }
+                seen[index] = true;
+
+                let terminator = match segments.get(i + 1) {
+                    Some(Segment::Literal(text)) => quote! { Some(#text) },
+                    _ => quote! { None },
+                };
+                let local = &locals[index];
+                let ty = members[index].1;
+                let description = format!("{}.{}", owner, name);
+                steps.push(quote! { let #local = s.field::<#ty>(#description, #terminator)?; });
+            }
+        }
+    }
+    if let Some(missing) = seen.iter().position(|s| !s) {
+        return Err(Error::new(
+            pattern.span(),
+            format!("pattern does not mention field {{{}}}", members[missing].0),
+        ));
+    }
+
+    let value = construct(&locals);
+    Ok(quote! {
+        crate::consume::parse_all(line, |s| {
+            #(#steps)*
+            Ok(#value)
+        })
+    })
+}
+
+fn construct(path: TokenStream, fields: &Fields, locals: &[Ident]) -> TokenStream {
+    match fields {
+        Fields::Named(named) => {
+            let names = named.named.iter().map(|f| &f.ident);
+            quote! { #path { #(#names: #locals),* } }
+        }
+        Fields::Unnamed(_) => quote! { #path(#(#locals),*) },
+        Fields::Unit => path,
+    }
+}
+
+pub fn derive(input: DeriveInput) -> Result<TokenStream> {
+    let name = &input.ident;
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let pattern = find_pattern(&input.attrs, name.span())?;
+            pattern_parser(&name.to_string(), &pattern, &data.fields, |locals| {
+                construct(quote! { Self }, &data.fields, locals)
+            })?
+        }
+        Data::Enum(data) => {
+            // Every variant is tried against the whole line; if none match, the error from the
+            // variant that got furthest is the one worth reporting.
+            let mut attempts = Vec::new();
+            for variant in &data.variants {
+                let pattern = find_pattern(&variant.attrs, variant.ident.span())?;
+                let ident = &variant.ident;
+                let owner = format!("{}::{}", name, ident);
+                attempts.push(pattern_parser(
+                    &owner,
+                    &pattern,
+                    &variant.fields,
+                    |locals| construct(quote! { Self::#ident }, &variant.fields, locals),
+                )?);
+            }
+
+            let (first, rest) = match attempts.split_first() {
+                Some(split) => split,
+                None => {
+                    return Err(Error::new(
+                        name.span(),
+                        "ParseLine needs at least one variant",
+                    ))
+                }
+            };
+            if rest.is_empty() {
+                return Ok(derive_impl(name, first.clone()));
+            }
+            quote! {
+                let mut error = match #first {
+                    Ok(v) => return Ok(v),
+                    Err(e) => e,
+                };
+                #(
+                    error = match #rest {
+                        Ok(v) => return Ok(v),
+                        Err(e) => error.furthest(e),
+                    };
+                )*
+                Err(error)
+            }
+        }
+        Data::Union(_) => {
+            return Err(Error::new(
+                name.span(),
+                "ParseLine cannot be derived for unions",
+            ))
+        }
+    };
+
+    Ok(derive_impl(name, body))
+}
+
+fn derive_impl(name: &Ident, body: TokenStream) -> TokenStream {
+    quote! {
+        impl std::str::FromStr for #name {
+            type Err = crate::consume::ParseError;
+
+            fn from_str(line: &str) -> Result<Self, Self::Err> {
+                #body
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syn::parse_quote;
+
+    fn error(input: DeriveInput) -> String {
+        derive(input).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_struct() {
+        let code = derive(parse_quote! {
+            #[pattern("{min}-{max} {ch}: {password}")]
+            struct Policy {
+                min: usize,
+                max: usize,
+                ch: char,
+                password: String,
+            }
+        })
+        .unwrap()
+        .to_string();
+        assert!(code.contains("impl std :: str :: FromStr for Policy"));
+        assert!(code.contains("s . field :: < usize > (\"Policy.min\" , Some (\"-\")) ?"));
+        assert!(code.contains("s . literal (\": \") ?"));
+        assert!(code.contains("s . field :: < String > (\"Policy.password\" , None) ?"));
+        assert!(code.contains(
+            "Self { min : field_min , max : field_max , ch : field_ch , password : field_password }"
+        ));
+    }
+
+    #[test]
+    fn test_enum() {
+        let code = derive(parse_quote! {
+            enum Command {
+                #[pattern("forward {0}")]
+                Forward(i64),
+                #[pattern("{{stop}}")]
+                Stop,
+            }
+        })
+        .unwrap()
+        .to_string();
+        assert!(code.contains("s . field :: < i64 > (\"Command::Forward.0\" , None) ?"));
+        assert!(code.contains("Self :: Forward (field_0)"));
+        assert!(code.contains("s . literal (\"{stop}\") ?"));
+        assert!(code.contains("error . furthest (e)"));
+
+        // A single variant has nothing to pick between
+        let code = derive(parse_quote! {
+            enum Only {
+                #[pattern("only {0}")]
+                Only(i64),
+            }
+        })
+        .unwrap()
+        .to_string();
+        assert!(!code.contains("furthest"));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            error(parse_quote! { struct S { a: i64 } }),
+            "missing #[pattern(\"...\")] attribute"
+        );
+        assert_eq!(
+            error(parse_quote! {
+                #[pattern("{a}")]
+                #[pattern("{a}")]
+                struct S { a: i64 }
+            }),
+            "duplicate #[pattern] attribute"
+        );
+        assert_eq!(
+            error(parse_quote! { #[pattern("{b}")] struct S { a: i64 } }),
+            "pattern refers to unknown field {b}"
+        );
+        assert_eq!(
+            error(parse_quote! { #[pattern("{a} {a}")] struct S { a: i64 } }),
+            "field {a} appears more than once"
+        );
+        assert_eq!(
+            error(parse_quote! { #[pattern("{a}")] struct S { a: i64, b: i64 } }),
+            "pattern does not mention field {b}"
+        );
+        assert_eq!(
+            error(parse_quote! { #[pattern("{a}{b}")] struct S { a: i64, b: i64 } }),
+            "fields {a} and {b} need literal text between them"
+        );
+        assert_eq!(
+            error(parse_quote! { #[pattern("{a")] struct S { a: i64 } }),
+            "unclosed '{' in pattern"
+        );
+        assert_eq!(
+            error(parse_quote! { #[pattern("a}")] struct S { a: i64 } }),
+            "unmatched '}' in pattern"
+        );
+        assert_eq!(
+            error(parse_quote! {
+                enum E {
+                    #[pattern("a")]
+                    A,
+                    B,
+                }
+            }),
+            "missing #[pattern(\"...\")] attribute"
+        );
+        assert_eq!(
+            error(parse_quote! { enum E {} }),
+            "ParseLine needs at least one variant"
+        );
+        assert_eq!(
+            error(parse_quote! { union U { a: i64 } }),
+            "ParseLine cannot be derived for unions"
+        );
+    }
+}
@@ -1 +1,442 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
 
+/// A zero-copy cursor over an input string. Everything handed back by the scanner borrows from
+/// the original input, so parsers only allocate when the caller decides to own the result.
+#[derive(Clone, Copy, Debug)]
+pub struct Scanner<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub offset: usize,
+    pub expected: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "expected {} at offset {}", self.expected, self.offset)
+    }
+}
+
+impl Error for ParseError {}
+
+impl ParseError {
+    /// Places the error within `text`, the input the scanner was run over, which starts at line
+    /// `first_line` of `source`. `text` may be a single line or a whole file.
+    pub fn locate(&self, source: &str, text: &str, first_line: usize) -> Diagnostic {
+        let offset = self.offset.min(text.len());
+        let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = text[offset..].find('\n').map_or(text.len(), |i| offset + i);
+
+        Diagnostic {
+            source: source.to_owned(),
+            line: first_line + text[..line_start].matches('\n').count(),
+            column: text[line_start..offset].chars().count() + 1,
+            text: text[line_start..line_end].trim_end_matches('\r').to_owned(),
+            message: format!("expected {}", self.expected),
+        }
+    }
+
+    /// Of two failed attempts at the same input, keeps whichever got further before failing. That
+    /// is usually the alternative the input was meant to match.
+    pub fn furthest(self, other: ParseError) -> ParseError {
+        if other.offset > self.offset {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+pub type ParseResult<T> = Result<T, ParseError>;
+
+/// A parse failure tied to a position in a named input, rendered in the style of a compiler
+/// error: the message, the location, then the offending line with a caret under the column.
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    pub source: String,
+    pub line: usize,
+    pub column: usize,
+    pub text: String,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let gutter = " ".repeat(self.line.to_string().len());
+        writeln!(f, "error: {}", self.message)?;
+        writeln!(
+            f,
+            "{}--> {}:{}:{}",
+            gutter, self.source, self.line, self.column
+        )?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", self.line, self.text)?;
+        write!(f, "{} | {}^", gutter, " ".repeat(self.column - 1))
+    }
+}
+
+impl Error for Diagnostic {}
+
+impl<'a> Scanner<'a> {
+    pub fn new(input: &'a str) -> Scanner<'a> {
+        Scanner { input, pos: 0 }
+    }
+
+    /// How far into the input the scanner is, in bytes.
+    pub fn offset(&self) -> usize {
+        self.pos
+    }
+
+    pub fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.input.len()
+    }
+
+    pub fn error<T, S: Into<String>>(&self, expected: S) -> ParseResult<T> {
+        Err(ParseError {
+            offset: self.pos,
+            expected: expected.into(),
+        })
+    }
+
+    fn advance(&mut self, n: usize) -> &'a str {
+        let taken = &self.input[self.pos..self.pos + n];
+        self.pos += n;
+        taken
+    }
+
+    /// Consumes the longest (possibly empty) prefix whose characters all satisfy `pred`.
+    pub fn take_while<F: Fn(char) -> bool>(&mut self, pred: F) -> &'a str {
+        let len = self
+            .rest()
+            .find(|c| !pred(c))
+            .unwrap_or_else(|| self.rest().len());
+        self.advance(len)
+    }
+
+    /// Like `take_while`, but fails unless at least one character matched.
+    pub fn take_while1<F: Fn(char) -> bool>(
+        &mut self,
+        pred: F,
+        what: &str,
+    ) -> ParseResult<&'a str> {
+        let taken = self.take_while(pred);
+        if taken.is_empty() {
+            self.error(what)
+        } else {
+            Ok(taken)
+        }
+    }
+
+    pub fn literal(&mut self, lit: &str) -> ParseResult<&'a str> {
+        if self.rest().starts_with(lit) {
+            Ok(self.advance(lit.len()))
+        } else {
+            self.error(format!("{:?}", lit))
+        }
+    }
+
+    pub fn whitespace(&mut self) -> &'a str {
+        self.take_while(char::is_whitespace)
+    }
+
+    /// Matches the first of `table`'s keywords that the input starts with, yielding its value.
+    pub fn keyword<T: Clone>(&mut self, table: &[(&str, T)]) -> ParseResult<T> {
+        for (word, value) in table {
+            if self.literal(word).is_ok() {
+                return Ok(value.clone());
+            }
+        }
+        let words: Vec<String> = table.iter().map(|(w, _)| format!("{:?}", w)).collect();
+        self.error(format!("one of {}", words.join(", ")))
+    }
+
+    /// An optionally signed run of decimal digits, parsed into whatever integer type the caller
+    /// asks for. Overflow is reported at the start of the number rather than where it ran out.
+    pub fn int<T: FromStr>(&mut self) -> ParseResult<T> {
+        let start = *self;
+        let sign_len = match self.rest().chars().next() {
+            Some('-') | Some('+') => 1,
+            _ => 0,
+        };
+        let digits = self.rest()[sign_len..]
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or_else(|| self.rest().len() - sign_len);
+        if digits == 0 {
+            return self.error("an integer");
+        }
+
+        let text = self.advance(sign_len + digits);
+        match text.parse::<T>() {
+            Ok(v) => Ok(v),
+            Err(_) => {
+                *self = start;
+                self.error(format!("an integer in range, found {}", text))
+            }
+        }
+    }
+
+    /// A run of alphanumeric characters or underscores.
+    pub fn ident(&mut self) -> ParseResult<&'a str> {
+        self.take_while1(|c| c.is_alphanumeric() || c == '_', "an identifier")
+    }
+
+    /// Takes everything up to the next occurrence of `terminator` (or the rest of the input when
+    /// there is none) and converts it with `FromStr`. `name` is only used to describe failures.
+    pub fn field<T>(&mut self, name: &str, terminator: Option<&str>) -> ParseResult<T>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let rest = self.rest();
+        let len = match terminator {
+            Some(t) => match rest.find(t) {
+                Some(len) => len,
+                None => return self.error(format!("{} followed by {:?}", name, t)),
+            },
+            None => rest.len(),
+        };
+
+        match rest[..len].parse::<T>() {
+            Ok(v) => {
+                self.advance(len);
+                Ok(v)
+            }
+            Err(e) => self.error(format!("{} ({}: {:?})", name, e, &rest[..len])),
+        }
+    }
+
+    /// Runs `f`, rewinding the scanner and returning `None` if it fails.
+    pub fn optional<T, F>(&mut self, f: F) -> Option<T>
+    where
+        F: FnOnce(&mut Self) -> ParseResult<T>,
+    {
+        let start = *self;
+        match f(self) {
+            Ok(v) => Some(v),
+            Err(_) => {
+                *self = start;
+                None
+            }
+        }
+    }
+
+    /// One or more `item`s separated by `sep`. Once a separator has matched, another item must
+    /// follow it, so a bad entry is reported where it is rather than as leftover input.
+    pub fn separated<T, F>(&mut self, sep: &str, mut item: F) -> ParseResult<Vec<T>>
+    where
+        F: FnMut(&mut Self) -> ParseResult<T>,
+    {
+        let mut items = vec![item(self)?];
+        while self.literal(sep).is_ok() {
+            items.push(item(self)?);
+        }
+        Ok(items)
+    }
+
+    pub fn end(&mut self) -> ParseResult<()> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            self.error("end of input")
+        }
+    }
+}
+
+/// Runs `f` over the whole of `input`, failing if anything is left over afterwards.
+pub fn parse_all<'a, T, F>(input: &'a str, f: F) -> ParseResult<T>
+where
+    F: FnOnce(&mut Scanner<'a>) -> ParseResult<T>,
+{
+    let mut s = Scanner::new(input);
+    let v = f(&mut s)?;
+    s.end()?;
+    Ok(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proc::ParseLine;
+
+    #[derive(Debug, PartialEq, ParseLine)]
+    #[pattern("{min}-{max} {ch}: {password}")]
+    struct Policy {
+        min: usize,
+        max: usize,
+        ch: char,
+        password: String,
+    }
+
+    #[derive(Debug, PartialEq, ParseLine)]
+    enum Command {
+        #[pattern("forward {0}")]
+        Forward(i64),
+        #[pattern("turn {degrees} {dir}")]
+        Turn { degrees: u32, dir: char },
+        #[pattern("stop")]
+        Stop,
+    }
+
+    #[test]
+    fn test_literal_and_ident() {
+        let mut s = Scanner::new("shiny gold bags");
+        assert_eq!(s.ident().unwrap(), "shiny");
+        assert_eq!(s.literal(" ").unwrap(), " ");
+        assert_eq!(s.ident().unwrap(), "gold");
+        assert_eq!(
+            s.literal(" bag,").unwrap_err(),
+            ParseError {
+                offset: 10,
+                expected: "\" bag,\"".to_owned()
+            }
+        );
+        assert_eq!(s.rest(), " bags");
+    }
+
+    #[test]
+    fn test_int() {
+        let mut s = Scanner::new("-12+7 x");
+        assert_eq!(s.int::<i32>().unwrap(), -12);
+        assert_eq!(s.int::<i32>().unwrap(), 7);
+        assert_eq!(s.int::<i32>().unwrap_err().offset, 5);
+
+        let mut s = Scanner::new("300");
+        assert_eq!(s.int::<u8>().unwrap_err().offset, 0);
+        assert_eq!(s.rest(), "300");
+    }
+
+    #[test]
+    fn test_separated() {
+        let v = parse_all("1, 2, 3", |s| s.separated(", ", |s| s.int::<u32>())).unwrap();
+        assert_eq!(v, vec![1, 2, 3]);
+
+        let mut s = Scanner::new("1,2;");
+        assert_eq!(s.separated(",", |s| s.int::<u32>()).unwrap(), vec![1, 2]);
+        assert_eq!(s.rest(), ";");
+
+        let mut s = Scanner::new("1,2,");
+        assert_eq!(s.separated(",", |s| s.int::<u32>()).unwrap_err().offset, 4);
+    }
+
+    #[test]
+    fn test_optional() {
+        let mut s = Scanner::new("bags.");
+        assert_eq!(s.literal("bag").unwrap(), "bag");
+        assert_eq!(s.optional(|s| s.literal("s")), Some("s"));
+        assert_eq!(s.optional(|s| s.literal("s")), None);
+        assert_eq!(s.rest(), ".");
+        assert_eq!(s.end().unwrap_err().offset, 4);
+    }
+
+    #[test]
+    fn test_field() {
+        let mut s = Scanner::new("1-3 a: abcde");
+        assert_eq!(s.field::<usize>("min", Some("-")).unwrap(), 1);
+        s.literal("-").unwrap();
+        assert_eq!(s.field::<usize>("max", Some(" ")).unwrap(), 3);
+        s.literal(" ").unwrap();
+
+        let err = s.field::<usize>("ch", Some(":")).unwrap_err();
+        assert_eq!(err.offset, 4);
+        assert_eq!(err.expected, "ch (invalid digit found in string: \"a\")");
+
+        assert_eq!(s.field::<char>("ch", Some(":")).unwrap(), 'a');
+        s.literal(": ").unwrap();
+        assert_eq!(s.field::<String>("password", None).unwrap(), "abcde");
+        assert!(s.end().is_ok());
+    }
+
+    #[test]
+    fn test_keyword() {
+        let mut s = Scanner::new("up 3");
+        let table = [("down", -1), ("up", 1)];
+        assert_eq!(s.keyword(&table).unwrap(), 1);
+        assert_eq!(
+            s.keyword(&table).unwrap_err().expected,
+            "one of \"down\", \"up\""
+        );
+    }
+
+    #[test]
+    fn test_diagnostic() {
+        let text = "1,2,3,\n4,x,6\n";
+        let err = parse_all(text, |s| {
+            s.separated(",", |s| {
+                s.whitespace();
+                s.int::<i64>()
+            })
+        })
+        .unwrap_err();
+        assert_eq!(
+            err.locate("prog.txt", text, 1).to_string(),
+            "error: expected an integer\n --> prog.txt:2:3\n  |\n2 | 4,x,6\n  |   ^"
+        );
+
+        let err = ParseError {
+            offset: 1,
+            expected: "\"-\"".to_owned(),
+        };
+        assert_eq!(
+            err.locate("in.txt", "1x3 a: abcde", 10).to_string(),
+            "error: expected \"-\"\n  --> in.txt:10:2\n   |\n10 | 1x3 a: abcde\n   |  ^"
+        );
+    }
+
+    #[test]
+    fn test_derive_parse_line() {
+        assert_eq!(
+            "1-3 a: abcde".parse::<Policy>().unwrap(),
+            Policy {
+                min: 1,
+                max: 3,
+                ch: 'a',
+                password: "abcde".to_owned()
+            }
+        );
+
+        let err = "1-3 ab: abcde".parse::<Policy>().unwrap_err();
+        assert_eq!(err.offset, 4);
+        assert_eq!(
+            err.expected,
+            "Policy.ch (too many characters in string: \"ab\")"
+        );
+
+        let err = "1-3 a abcde".parse::<Policy>().unwrap_err();
+        assert_eq!(err.expected, "Policy.ch followed by \": \"");
+    }
+
+    #[test]
+    fn test_derive_parse_line_enum() {
+        assert_eq!("forward 5".parse::<Command>().unwrap(), Command::Forward(5));
+        assert_eq!(
+            "turn 90 L".parse::<Command>().unwrap(),
+            Command::Turn {
+                degrees: 90,
+                dir: 'L'
+            }
+        );
+        assert_eq!("stop".parse::<Command>().unwrap(), Command::Stop);
+
+        // The variant that got furthest is the one reported
+        let err = "turn left L".parse::<Command>().unwrap_err();
+        assert_eq!(err.offset, 5);
+        assert_eq!(
+            err.expected,
+            "Command::Turn.degrees (invalid digit found in string: \"left\")"
+        );
+
+        // And on a tie, the first one tried
+        let err = "jump 3".parse::<Command>().unwrap_err();
+        assert_eq!(err.offset, 0);
+        assert_eq!(err.expected, "\"forward \"");
+    }
+}
@@ -1,6 +1,10 @@
+use crate::consume::{parse_all, Diagnostic, ParseError, ParseResult};
+use anyhow::Context;
+use std::fmt;
 use std::fs::File;
 use std::io::{self, BufRead};
 use std::path::Path;
+use std::str::FromStr;
 
 pub fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
 where
@@ -10,15 +14,313 @@ where
     Ok(io::BufReader::new(file).lines())
 }
 
-pub fn read_csints<P>(filename: P) -> Result<Vec<i64>, anyhow::Error>
+/// Parses every line of `filename` with `parse`. A failure is reported as a diagnostic pointing
+/// at the file, line and column it happened at.
+pub fn parse_lines_with<P, T, F>(filename: P, mut parse: F) -> Result<Vec<T>, anyhow::Error>
 where
     P: AsRef<Path>,
+    F: FnMut(&str) -> ParseResult<T>,
 {
+    let source = filename.as_ref().display().to_string();
     let mut result = Vec::new();
-    let contents = std::fs::read_to_string(filename)?;
-    for num in contents.split(",") {
-        result.push(num.trim().parse::<i64>()?);
+    for (i, maybe_line) in read_lines(&filename)?.enumerate() {
+        let line = maybe_line?;
+        result.push(parse(&line).map_err(|e| e.locate(&source, &line, i + 1))?);
     }
 
     Ok(result)
 }
+
+pub fn parse_lines<P, T>(filename: P) -> Result<Vec<T>, anyhow::Error>
+where
+    P: AsRef<Path>,
+    T: FromStr<Err = ParseError>,
+{
+    parse_lines_with(filename, str::parse)
+}
+
+/// Pulls every integer out of `s` in order, ignoring whatever text surrounds them. A '-' is
+/// taken as a sign only when it doesn't directly follow a letter or digit, so "1-3" is 1 and 3
+/// while "x=-3" is -3.
+pub fn ints<T: FromStr>(s: &str) -> Result<Vec<T>, T::Err> {
+    let bytes = s.as_bytes();
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if !bytes[i].is_ascii_digit() {
+            i += 1;
+            continue;
+        }
+
+        let mut start = i;
+        if start > 0
+            && bytes[start - 1] == b'-'
+            && (start < 2 || !bytes[start - 2].is_ascii_alphanumeric())
+        {
+            start -= 1;
+        }
+        while i < bytes.len() && bytes[i].is_ascii_digit() {
+            i += 1;
+        }
+        result.push(s[start..i].parse::<T>()?);
+    }
+
+    Ok(result)
+}
+
+pub fn read_ints<P, T>(filename: P) -> Result<Vec<T>, anyhow::Error>
+where
+    P: AsRef<Path>,
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    Ok(ints(&std::fs::read_to_string(filename)?)?)
+}
+
+pub fn read_csints<P>(filename: P) -> Result<Vec<i64>, anyhow::Error>
+where
+    P: AsRef<Path>,
+{
+    let contents = std::fs::read_to_string(&filename)?;
+    let result = parse_all(&contents, |s| {
+        s.separated(",", |s| {
+            s.whitespace();
+            let num = s.int::<i64>()?;
+            s.whitespace();
+            Ok(num)
+        })
+    })
+    .map_err(|e| e.locate(&filename.as_ref().display().to_string(), &contents, 1))?;
+
+    Ok(result)
+}
+
+/// A whole input file held in memory, for formats that are easier to pick apart as a unit than
+/// line by line.
+pub struct InputFile {
+    pub source: String,
+    pub contents: String,
+}
+
+pub fn read_input<P>(filename: P) -> io::Result<InputFile>
+where
+    P: AsRef<Path>,
+{
+    Ok(InputFile {
+        source: filename.as_ref().display().to_string(),
+        contents: std::fs::read_to_string(filename)?,
+    })
+}
+
+impl InputFile {
+    pub fn sections(&self) -> Sections<'_> {
+        Sections {
+            source: &self.source,
+            text: &self.contents,
+            pos: 0,
+        }
+    }
+}
+
+/// Walks an input made of header lines and blank-line separated blocks, handing each piece out
+/// under the name the caller expects it to have. When the input runs out early or has something
+/// left over, the error says which section was expected and where.
+pub struct Sections<'a> {
+    source: &'a str,
+    text: &'a str,
+    pos: usize,
+}
+
+pub struct Section<'a> {
+    pub name: &'static str,
+    pub text: &'a str,
+    source: &'a str,
+    line: usize,
+}
+
+impl<'a> Sections<'a> {
+    fn error(&self, offset: usize, expected: String) -> Diagnostic {
+        ParseError { offset, expected }.locate(self.source, self.text, 1)
+    }
+
+    fn section(&self, name: &'static str, start: usize, end: usize) -> Section<'a> {
+        Section {
+            name,
+            text: &self.text[start..end],
+            source: self.source,
+            line: 1 + self.text[..start].matches('\n').count(),
+        }
+    }
+
+    fn line_end(&self, from: usize) -> usize {
+        self.text[from..]
+            .find('\n')
+            .map_or(self.text.len(), |i| from + i)
+    }
+
+    fn skip_blank_lines(&mut self) {
+        while self.pos < self.text.len() {
+            let end = self.line_end(self.pos);
+            if !self.text[self.pos..end].trim().is_empty() {
+                break;
+            }
+            self.pos = (end + 1).min(self.text.len());
+        }
+    }
+
+    /// The next line, exactly as it is.
+    pub fn line(&mut self, name: &'static str) -> Result<Section<'a>, Diagnostic> {
+        if self.pos >= self.text.len() {
+            return Err(self.error(self.pos, format!("a line with {}", name)));
+        }
+        let end = self.line_end(self.pos);
+        let section = self.section(name, self.pos, end);
+        self.pos = (end + 1).min(self.text.len());
+        Ok(section)
+    }
+
+    /// The next run of non-blank lines, skipping any blank lines before it.
+    pub fn block(&mut self, name: &'static str) -> Result<Section<'a>, Diagnostic> {
+        self.skip_blank_lines();
+        if self.pos >= self.text.len() {
+            return Err(self.error(self.pos, format!("a block of {}", name)));
+        }
+
+        let start = self.pos;
+        let mut end = self.line_end(start);
+        while end < self.text.len() {
+            let next_end = self.line_end(end + 1);
+            if self.text[end + 1..next_end].trim().is_empty() {
+                break;
+            }
+            end = next_end;
+        }
+        self.pos = (end + 1).min(self.text.len());
+        Ok(self.section(name, start, end))
+    }
+
+    /// Every remaining block, of which there must be at least one.
+    pub fn blocks(&mut self, name: &'static str) -> Result<Vec<Section<'a>>, Diagnostic> {
+        let mut blocks = vec![self.block(name)?];
+        self.skip_blank_lines();
+        while self.pos < self.text.len() {
+            blocks.push(self.block(name)?);
+            self.skip_blank_lines();
+        }
+        Ok(blocks)
+    }
+
+    /// Checks that nothing but blank lines is left.
+    pub fn end(&mut self) -> Result<(), Diagnostic> {
+        self.skip_blank_lines();
+        if self.pos < self.text.len() {
+            Err(self.error(self.pos, "end of input".to_owned()))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<'a> Section<'a> {
+    pub fn ints<T>(&self) -> Result<Vec<T>, anyhow::Error>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        ints(self.text).with_context(|| format!("Malformed integer in {}", self))
+    }
+}
+
+impl fmt::Display for Section<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}:{}", self.name, self.source, self.line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ints() {
+        assert_eq!(ints::<i64>("").unwrap(), vec![]);
+        assert_eq!(
+            ints::<i64>("939\n7,13,x,x,59").unwrap(),
+            vec![939, 7, 13, 59]
+        );
+        assert_eq!(
+            ints::<i64>("22 13 17 11  0\n 8  2 23").unwrap(),
+            vec![22, 13, 17, 11, 0, 8, 2, 23]
+        );
+        assert_eq!(
+            ints::<i64>("-4 x=-12, y=+7..-3").unwrap(),
+            vec![-4, -12, 7, -3]
+        );
+        assert_eq!(ints::<i64>("1-3 a: abcde").unwrap(), vec![1, 3]);
+        assert_eq!(ints::<i64>("a-1 --2").unwrap(), vec![1, -2]);
+    }
+
+    #[test]
+    fn test_ints_bad_type() {
+        assert!(ints::<u8>("1,256").is_err());
+        assert!(ints::<u32>("5 -3").is_err());
+    }
+
+    fn input(contents: &str) -> InputFile {
+        InputFile {
+            source: "in.txt".to_owned(),
+            contents: contents.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_sections() {
+        let file = input("7,4,9\n\n22 13\n 8  2\n\n\n3 15\n 9 18\n");
+        let mut sections = file.sections();
+        let calls = sections.line("called numbers").unwrap();
+        assert_eq!(calls.ints::<u32>().unwrap(), vec![7, 4, 9]);
+
+        let boards = sections.blocks("boards").unwrap();
+        assert_eq!(boards.len(), 2);
+        assert_eq!(boards[0].text, "22 13\n 8  2");
+        assert_eq!(boards[1].to_string(), "boards at in.txt:7");
+        assert!(sections.end().is_ok());
+
+        let file = input("939\n7,13,x,x,59");
+        let mut sections = file.sections();
+        assert_eq!(sections.line("arrival").unwrap().text, "939");
+        assert_eq!(sections.line("schedule").unwrap().text, "7,13,x,x,59");
+        assert!(sections.end().is_ok());
+    }
+
+    #[test]
+    fn test_section_errors() {
+        let file = input("939\n");
+        let mut sections = file.sections();
+        sections.line("arrival").unwrap();
+        let err = sections.line("schedule").err().unwrap();
+        assert_eq!(err.message, "expected a line with schedule");
+        assert_eq!((err.line, err.column), (2, 1));
+
+        let file = input("1,2\n\n1 2\n\n");
+        let mut sections = file.sections();
+        sections.line("called numbers").unwrap();
+        sections.block("boards").unwrap();
+        assert!(sections.block("boards").is_err());
+
+        let file = input("939\n7,13\n\nleftover\n");
+        let mut sections = file.sections();
+        sections.line("arrival").unwrap();
+        sections.line("schedule").unwrap();
+        let err = sections.end().unwrap_err();
+        assert_eq!((err.line, err.text.as_str()), (4, "leftover"));
+
+        let err = input("1 x 99999999999")
+            .sections()
+            .line("numbers")
+            .unwrap()
+            .ints::<u32>()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Malformed integer in numbers at in.txt:1");
+    }
+}
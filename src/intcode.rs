@@ -1,60 +1,212 @@
+use std::collections::VecDeque;
 use std::error::Error;
 use std::fmt;
-use std::ops::{Deref, DerefMut};
+use std::str::FromStr;
 
 extern crate proc;
 
-enum VMState {
+mod asm;
+mod cache;
+mod cfg;
+mod debug;
+mod decompile;
+mod disasm;
+mod history;
+mod loops;
+mod memory;
+mod network;
+mod profile;
+mod snapshot;
+pub mod tools;
+mod trace;
+mod transpile;
+mod word;
+
+pub use asm::assemble;
+use cache::{DecodeCache, DecodedOp};
+pub use cfg::Cfg;
+pub use debug::Debugger;
+pub use decompile::decompile;
+pub use disasm::disassemble;
+pub use history::History;
+use loops::LoopDetector;
+pub use memory::{IntcodeMemory, PagedMemory, ZeroedMemory};
+pub use network::{IntcodeNetwork, NetworkOutcome};
+pub use profile::Profile;
+pub use snapshot::Snapshot;
+pub use trace::{Trace, TraceEntry};
+pub use transpile::transpile;
+pub use word::Word;
+
+#[derive(Clone, Copy)]
+enum VMState<W> {
     Ready,
     Fault,
     WaitingForInput(SomeOutOpArg),
-    WaitingForOutput(SomeInOpArg),
+    WaitingForOutput(SomeInOpArg<W>),
+}
+
+/// The VM's state as seen from outside, without the pending operand a waiting instruction holds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VMStatus {
+    Ready,
+    Fault,
+    WaitingForInput,
+    WaitingForOutput,
 }
 
 pub struct IntcodeVM<T>
 where
-    T: DerefMut<Target = [i64]>,
+    T: IntcodeMemory,
 {
     ip: usize,
     data: T,
-    state: VMState,
+    state: VMState<T::Word>,
     relative_base: i64,
+    inputs: VecDeque<T::Word>,
+    trace: Option<Trace<T::Word>>,
+    history: Option<History<T::Word>>,
+    profile: Option<Profile>,
+    cache: Option<DecodeCache<T>>,
+    loops: Option<LoopDetector<T::Word>>,
+    opcodes: Vec<(&'static OpcodeInfo, OpcodeHandler<T>)>,
+    arithmetic: Arithmetic,
 }
 
-#[derive(Debug)]
-pub enum IntcodeError {
+/// What went wrong, without where. Instructions fail with one of these and the VM wraps it in
+/// an `IntcodeError` on the way out.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IntcodeErrorKind {
     OutOfBoundsDereference(i64),
     IllegalOpcode(i64),
     OutOfBoundsArguments(i64),
     OutOfBoundsIp(i64),
     IllegalState,
     IllegalStore,
+    InputExhausted,
+    Overflow,
+    /// The VM failed earlier and won't run again until its state is replaced.
+    Faulted,
+    /// A run that was meant to go to completion was stopped by something other than the
+    /// program halting, such as the loop detector or a trap.
+    Interrupted(InterruptReason),
 }
 
-impl fmt::Display for IntcodeError {
+impl fmt::Display for IntcodeErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            IntcodeError::OutOfBoundsDereference(a) => {
-                write!(f, "IntcodeError: Out of bounds dereference at {}", a)
+            IntcodeErrorKind::OutOfBoundsDereference(a) => {
+                write!(f, "Out of bounds dereference at {}", a)
             }
-            IntcodeError::IllegalOpcode(a) => write!(f, "IntcodeError: Hit illegal opcode ({})", a),
-            IntcodeError::OutOfBoundsArguments(a) => {
-                write!(f, "IntcodeError: Out of bounds arguments at {}", a)
+            IntcodeErrorKind::IllegalOpcode(a) => write!(f, "Hit illegal opcode ({})", a),
+            IntcodeErrorKind::OutOfBoundsArguments(a) => {
+                write!(f, "Out of bounds arguments at {}", a)
+            }
+            IntcodeErrorKind::OutOfBoundsIp(a) => write!(f, "Out of bounds ip at {}", a),
+            IntcodeErrorKind::IllegalState => write!(f, "Illegal state"),
+            IntcodeErrorKind::IllegalStore => write!(f, "Illegal store"),
+            IntcodeErrorKind::InputExhausted => write!(f, "Waiting for input with none queued"),
+            IntcodeErrorKind::Overflow => write!(f, "Arithmetic overflow"),
+            IntcodeErrorKind::Faulted => write!(f, "VM has already faulted"),
+            IntcodeErrorKind::Interrupted(reason) => {
+                write!(f, "Stopped before halting ({:?})", reason)
             }
-            IntcodeError::OutOfBoundsIp(a) => write!(f, "IntcodeError: Out of bounds ip at {}", a),
-            IntcodeError::IllegalState => write!(f, "IntcodeError: Illegal state"),
-            IntcodeError::IllegalStore => write!(f, "IntcodeError: Illegal store"),
         }
     }
 }
 
-impl Error for IntcodeError {}
+// How many words either side of the ip an error keeps
+const WINDOW_BEFORE: usize = 4;
+const WINDOW_AFTER: usize = 8;
 
-#[derive(Debug, PartialEq)]
+/// An error along with the VM's registers when it happened and the memory around the ip.
+#[derive(Debug)]
+pub struct IntcodeError<W = i64> {
+    pub kind: IntcodeErrorKind,
+    pub ip: usize,
+    /// The word at the ip, unless the ip is out of bounds.
+    pub instruction: Option<W>,
+    pub relative_base: i64,
+    /// Address of the first word in `memory`.
+    pub memory_start: usize,
+    pub memory: Vec<W>,
+}
+
+impl<W: fmt::Display> fmt::Display for IntcodeError<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "IntcodeError: {} at ip {}", self.kind, self.ip)?;
+        if let Some(instruction) = &self.instruction {
+            write!(f, " (instruction {})", instruction)?;
+        }
+        write!(
+            f,
+            ", relative base {}, memory from {}:",
+            self.relative_base, self.memory_start
+        )?;
+        for (address, word) in (self.memory_start..).zip(&self.memory) {
+            match address == self.ip {
+                true => write!(f, " [{}]", word)?,
+                false => write!(f, " {}", word)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W: fmt::Debug + fmt::Display> Error for IntcodeError<W> {}
+
+/// What `add` and `mul` do when the result doesn't fit in a word. Whichever it is, the result
+/// is the same in debug and release builds.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Arithmetic {
+    /// Fail with `IntcodeErrorKind::Overflow`.
+    #[default]
+    Checked,
+    /// Wrap around in two's complement.
+    Wrapping,
+    /// Clamp to the word's minimum or maximum.
+    Saturating,
+}
+
+impl FromStr for Arithmetic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "checked" => Ok(Arithmetic::Checked),
+            "wrapping" => Ok(Arithmetic::Wrapping),
+            "saturating" => Ok(Arithmetic::Saturating),
+            _ => Err(format!(
+                "Unknown arithmetic {}, expected checked, wrapping or saturating",
+                s
+            )),
+        }
+    }
+}
+
+impl Arithmetic {
+    #[inline]
+    pub fn add<W: Word>(self, a: W, b: W) -> Result<W, IntcodeErrorKind> {
+        a.add_with(&b, self).ok_or(IntcodeErrorKind::Overflow)
+    }
+
+    #[inline]
+    pub fn mul<W: Word>(self, a: W, b: W) -> Result<W, IntcodeErrorKind> {
+        a.mul_with(&b, self).ok_or(IntcodeErrorKind::Overflow)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum InterruptReason {
     Terminate,
     WaitingForInput,
     WaitingForOutput,
+    /// `run_with_fuel` used up all the fuel it was given.
+    OutOfFuel,
+    /// The loop detector saw the VM return to a state it had already been in.
+    InfiniteLoop,
+    /// An opcode added with `IntcodeVM::add_opcode` asked to stop here.
+    Trap,
 }
 
 #[derive(Debug, PartialEq)]
@@ -65,18 +217,20 @@ pub enum StepResult {
 }
 
 #[derive(Clone, Copy)]
-enum SomeInOpArg {
+enum SomeInOpArg<W> {
     Position(PositionInput),
-    Immediate(ImmediateInput),
+    Immediate(ImmediateInput<W>),
     Relative(RelativeInput),
 }
 
-trait InOpArg {
-    fn read<T>(&self, vm: &IntcodeVM<T>) -> Result<i64, IntcodeError>
+// Operands that address memory hold an i64 however wide the word they were read from, since
+// anything wider is out of bounds anyway.
+trait InOpArg<W: Word> {
+    fn read<T>(&self, vm: &IntcodeVM<T>) -> Result<W, IntcodeErrorKind>
     where
-        T: DerefMut<Target = [i64]>;
-    fn to_enum(self) -> SomeInOpArg;
-    /*fn from(i: i64) -> Self; This is just for proc...*/
+        T: IntcodeMemory<Word = W>;
+    fn to_enum(self) -> SomeInOpArg<W>;
+    /*fn from(i: W) -> Self; This is just for proc...*/
 }
 
 #[derive(Clone, Copy)]
@@ -84,45 +238,47 @@ struct PositionInput {
     i: i64,
 }
 
-impl InOpArg for PositionInput {
-    fn read<T>(&self, vm: &IntcodeVM<T>) -> Result<i64, IntcodeError>
+impl<W: Word> InOpArg<W> for PositionInput {
+    fn read<T>(&self, vm: &IntcodeVM<T>) -> Result<W, IntcodeErrorKind>
     where
-        T: DerefMut<Target = [i64]>,
+        T: IntcodeMemory<Word = W>,
     {
         read_index(&vm.data, self.i)
     }
 
-    fn to_enum(self) -> SomeInOpArg {
+    fn to_enum(self) -> SomeInOpArg<W> {
         SomeInOpArg::Position(self)
     }
 }
 
 impl PositionInput {
-    fn from(i: i64) -> Self {
-        Self { i }
+    fn from<W: Word>(i: W) -> Self {
+        Self {
+            i: i.clamp_to_i64(),
+        }
     }
 }
 
 #[derive(Clone, Copy)]
-struct ImmediateInput {
-    i: i64,
+struct ImmediateInput<W> {
+    i: W,
 }
 
-impl InOpArg for ImmediateInput {
-    fn read<T>(&self, _: &IntcodeVM<T>) -> Result<i64, IntcodeError>
+impl<W: Word> InOpArg<W> for ImmediateInput<W> {
+    fn read<T>(&self, _: &IntcodeVM<T>) -> Result<W, IntcodeErrorKind>
     where
-        T: DerefMut<Target = [i64]>,
+        T: IntcodeMemory<Word = W>,
     {
-        Ok(self.i)
+        Ok(self.i.clone())
     }
 
-    fn to_enum(self) -> SomeInOpArg {
+    fn to_enum(self) -> SomeInOpArg<W> {
         SomeInOpArg::Immediate(self)
     }
 }
 
-impl ImmediateInput {
-    fn from(i: i64) -> Self {
+impl<W: Word> ImmediateInput<W> {
+    fn from(i: W) -> Self {
         Self { i }
     }
 }
@@ -132,22 +288,24 @@ struct RelativeInput {
     i: i64,
 }
 
-impl InOpArg for RelativeInput {
-    fn read<T>(&self, vm: &IntcodeVM<T>) -> Result<i64, IntcodeError>
+impl<W: Word> InOpArg<W> for RelativeInput {
+    fn read<T>(&self, vm: &IntcodeVM<T>) -> Result<W, IntcodeErrorKind>
     where
-        T: DerefMut<Target = [i64]>,
+        T: IntcodeMemory<Word = W>,
     {
-        read_index(&vm.data, vm.relative_base + self.i)
+        read_index(&vm.data, vm.relative_base.saturating_add(self.i))
     }
 
-    fn to_enum(self) -> SomeInOpArg {
+    fn to_enum(self) -> SomeInOpArg<W> {
         SomeInOpArg::Relative(self)
     }
 }
 
 impl RelativeInput {
-    fn from(i: i64) -> Self {
-        Self { i }
+    fn from<W: Word>(i: W) -> Self {
+        Self {
+            i: i.clamp_to_i64(),
+        }
     }
 }
 
@@ -158,11 +316,11 @@ enum SomeOutOpArg {
 }
 
 trait OutOpArg {
-    fn write<'a, T>(&self, vm: &'a mut IntcodeVM<T>) -> Result<&'a mut i64, IntcodeError>
+    fn write<'a, T>(&self, vm: &'a mut IntcodeVM<T>) -> Result<&'a mut T::Word, IntcodeErrorKind>
     where
-        T: DerefMut<Target = [i64]>;
+        T: IntcodeMemory;
     fn to_enum(self) -> SomeOutOpArg;
-    /*fn from(i: i64) -> Self;*/
+    /*fn from(i: W) -> Self;*/
 }
 
 #[derive(Clone, Copy)]
@@ -171,11 +329,11 @@ struct PositionOutput {
 }
 
 impl OutOpArg for PositionOutput {
-    fn write<'a, T>(&self, vm: &'a mut IntcodeVM<T>) -> Result<&'a mut i64, IntcodeError>
+    fn write<'a, T>(&self, vm: &'a mut IntcodeVM<T>) -> Result<&'a mut T::Word, IntcodeErrorKind>
     where
-        T: DerefMut<Target = [i64]>,
+        T: IntcodeMemory,
     {
-        read_index_mut(&mut vm.data, self.i)
+        vm.store(self.i)
     }
 
     fn to_enum(self) -> SomeOutOpArg {
@@ -184,8 +342,10 @@ impl OutOpArg for PositionOutput {
 }
 
 impl PositionOutput {
-    fn from(i: i64) -> Self {
-        Self { i }
+    fn from<W: Word>(i: W) -> Self {
+        Self {
+            i: i.clamp_to_i64(),
+        }
     }
 }
 
@@ -195,11 +355,11 @@ struct RelativeOutput {
 }
 
 impl OutOpArg for RelativeOutput {
-    fn write<'a, T>(&self, vm: &'a mut IntcodeVM<T>) -> Result<&'a mut i64, IntcodeError>
+    fn write<'a, T>(&self, vm: &'a mut IntcodeVM<T>) -> Result<&'a mut T::Word, IntcodeErrorKind>
     where
-        T: DerefMut<Target = [i64]>,
+        T: IntcodeMemory,
     {
-        read_index_mut(&mut vm.data, vm.relative_base + self.i)
+        vm.store(vm.relative_base.saturating_add(self.i))
     }
 
     fn to_enum(self) -> SomeOutOpArg {
@@ -207,95 +367,109 @@ impl OutOpArg for RelativeOutput {
     }
 }
 impl RelativeOutput {
-    fn from(i: i64) -> Self {
-        Self { i }
+    fn from<W: Word>(i: W) -> Self {
+        Self {
+            i: i.clamp_to_i64(),
+        }
     }
 }
 
-fn read_index<D: Deref<Target = [i64]>>(data: &D, index: i64) -> Result<i64, IntcodeError> {
+fn read_index<D: IntcodeMemory>(data: &D, index: i64) -> Result<D::Word, IntcodeErrorKind> {
     if index < 0 {
-        return Err(IntcodeError::OutOfBoundsDereference(index));
+        return Err(IntcodeErrorKind::OutOfBoundsDereference(index));
     }
-    match data.get(index as usize) {
-        Some(x) => Ok(*x),
-        None => Err(IntcodeError::OutOfBoundsDereference(index)),
+    match data.load(index as usize) {
+        Some(x) => Ok(x),
+        None => Err(IntcodeErrorKind::OutOfBoundsDereference(index)),
     }
 }
 
-fn read_index_mut<D: DerefMut<Target = [i64]>>(
+fn read_index_mut<D: IntcodeMemory>(
     data: &mut D,
     index: i64,
-) -> Result<&mut i64, IntcodeError> {
+) -> Result<&mut D::Word, IntcodeErrorKind> {
     if index < 0 {
-        return Err(IntcodeError::OutOfBoundsDereference(index));
+        return Err(IntcodeErrorKind::OutOfBoundsDereference(index));
     }
-    match data.get_mut(index as usize) {
+    match data.load_mut(index as usize) {
         Some(x) => Ok(x),
-        None => Err(IntcodeError::OutOfBoundsDereference(index)),
+        None => Err(IntcodeErrorKind::OutOfBoundsDereference(index)),
     }
 }
 
-fn intcode_op_add<D: DerefMut<Target = [i64]>, I1: InOpArg, I2: InOpArg, O: OutOpArg>(
+fn intcode_op_add<D: IntcodeMemory, I1: InOpArg<D::Word>, I2: InOpArg<D::Word>, O: OutOpArg>(
     vm: &mut IntcodeVM<D>,
     i1: I1,
     i2: I2,
     o: O,
-) -> Result<StepResult, IntcodeError> {
+) -> Result<StepResult, IntcodeErrorKind> {
     let op_1 = i1.read(vm)?;
     let op_2 = i2.read(vm)?;
 
-    *o.write(vm)? = op_1 + op_2;
+    let result = vm.arithmetic.add(op_1, op_2)?;
+    *o.write(vm)? = result;
 
     Ok(StepResult::Continue)
 }
 
-fn intcode_op_mul<D: DerefMut<Target = [i64]>, I1: InOpArg, I2: InOpArg, O: OutOpArg>(
+fn intcode_op_mul<D: IntcodeMemory, I1: InOpArg<D::Word>, I2: InOpArg<D::Word>, O: OutOpArg>(
     vm: &mut IntcodeVM<D>,
     i1: I1,
     i2: I2,
     o: O,
-) -> Result<StepResult, IntcodeError> {
+) -> Result<StepResult, IntcodeErrorKind> {
     let op_1 = i1.read(vm)?;
     let op_2 = i2.read(vm)?;
 
-    *o.write(vm)? = op_1 * op_2;
+    let result = vm.arithmetic.mul(op_1, op_2)?;
+    *o.write(vm)? = result;
 
     Ok(StepResult::Continue)
 }
 
-fn intcode_op_input<D: DerefMut<Target = [i64]>, O: OutOpArg>(
+fn intcode_op_input<D: IntcodeMemory, O: OutOpArg>(
     vm: &mut IntcodeVM<D>,
     store_idx: O,
-) -> Result<StepResult, IntcodeError> {
+) -> Result<StepResult, IntcodeErrorKind> {
+    // Queued input is consumed in place; the VM only interrupts once the queue runs dry. The
+    // input stays queued until it's stored, and a `run` resumed after an interrupt with input
+    // pushed in the meantime ends up here, so the VM stops waiting.
+    if let Some(i) = vm.inputs.front().cloned() {
+        *store_idx.write(vm)? = i;
+        vm.inputs.pop_front();
+        vm.state = VMState::Ready;
+        return Ok(StepResult::Continue);
+    }
+
     vm.state = VMState::WaitingForInput(store_idx.to_enum());
     Ok(StepResult::Interrupt(InterruptReason::WaitingForInput))
 }
 
-fn intcode_op_output<D: DerefMut<Target = [i64]>, I: InOpArg>(
+fn intcode_op_output<D: IntcodeMemory, I: InOpArg<D::Word>>(
     vm: &mut IntcodeVM<D>,
     read_idx: I,
-) -> Result<StepResult, IntcodeError> {
+) -> Result<StepResult, IntcodeErrorKind> {
     vm.state = VMState::WaitingForOutput(read_idx.to_enum());
     Ok(StepResult::Interrupt(InterruptReason::WaitingForOutput))
 }
 
 fn intcode_op_jump_test<
-    D: DerefMut<Target = [i64]>,
-    I1: InOpArg,
-    I2: InOpArg,
-    F: Fn(i64) -> bool,
+    D: IntcodeMemory,
+    I1: InOpArg<D::Word>,
+    I2: InOpArg<D::Word>,
+    F: Fn(&D::Word) -> bool,
 >(
     vm: &mut IntcodeVM<D>,
     val: I1,
     jump: I2,
     test: F,
-) -> Result<StepResult, IntcodeError> {
+) -> Result<StepResult, IntcodeErrorKind> {
     let v = val.read(&vm)?;
 
-    if test(v) {
-        let new_ip = jump.read(vm)?;
+    if test(&v) {
+        let new_ip = jump.read(vm)?.clamp_to_i64();
         if new_ip < 0 {
-            return Err(IntcodeError::OutOfBoundsIp(new_ip));
+            return Err(IntcodeErrorKind::OutOfBoundsIp(new_ip));
         }
 
         vm.ip = new_ip as usize;
@@ -305,195 +479,498 @@ fn intcode_op_jump_test<
     }
 }
 
-fn intcode_op_jump_if_true<D: DerefMut<Target = [i64]>, I1: InOpArg, I2: InOpArg>(
+fn intcode_op_jump_if_true<D: IntcodeMemory, I1: InOpArg<D::Word>, I2: InOpArg<D::Word>>(
     vm: &mut IntcodeVM<D>,
     test: I1,
     jump: I2,
-) -> Result<StepResult, IntcodeError> {
-    intcode_op_jump_test(vm, test, jump, |v| v != 0)
+) -> Result<StepResult, IntcodeErrorKind> {
+    intcode_op_jump_test(vm, test, jump, |v| !v.is_zero())
 }
 
-fn intcode_op_jump_if_false<D: DerefMut<Target = [i64]>, I1: InOpArg, I2: InOpArg>(
+fn intcode_op_jump_if_false<D: IntcodeMemory, I1: InOpArg<D::Word>, I2: InOpArg<D::Word>>(
     vm: &mut IntcodeVM<D>,
     test: I1,
     jump: I2,
-) -> Result<StepResult, IntcodeError> {
-    intcode_op_jump_test(vm, test, jump, |v| v == 0)
+) -> Result<StepResult, IntcodeErrorKind> {
+    intcode_op_jump_test(vm, test, jump, |v| v.is_zero())
 }
 
 fn intcode_op_conditional_store<
-    D: DerefMut<Target = [i64]>,
-    I1: InOpArg,
-    I2: InOpArg,
+    D: IntcodeMemory,
+    I1: InOpArg<D::Word>,
+    I2: InOpArg<D::Word>,
     O: OutOpArg,
-    F: Fn(i64, i64) -> bool,
+    F: Fn(&D::Word, &D::Word) -> bool,
 >(
     vm: &mut IntcodeVM<D>,
     val_a: I1,
     val_b: I2,
     store: O,
     test: F,
-) -> Result<StepResult, IntcodeError> {
+) -> Result<StepResult, IntcodeErrorKind> {
     let v_a = val_a.read(vm)?;
     let v_b = val_b.read(vm)?;
 
-    *store.write(vm)? = if test(v_a, v_b) { 1 } else { 0 };
+    *store.write(vm)? = D::Word::from_i64(if test(&v_a, &v_b) { 1 } else { 0 });
 
     Ok(StepResult::Continue)
 }
 
-fn intcode_op_less_than<D: DerefMut<Target = [i64]>, I1: InOpArg, I2: InOpArg, O: OutOpArg>(
+fn intcode_op_less_than<
+    D: IntcodeMemory,
+    I1: InOpArg<D::Word>,
+    I2: InOpArg<D::Word>,
+    O: OutOpArg,
+>(
     vm: &mut IntcodeVM<D>,
     val_a: I1,
     val_b: I2,
     store: O,
-) -> Result<StepResult, IntcodeError> {
+) -> Result<StepResult, IntcodeErrorKind> {
     intcode_op_conditional_store(vm, val_a, val_b, store, |a, b| a < b)
 }
 
-fn intcode_op_equals<D: DerefMut<Target = [i64]>, I1: InOpArg, I2: InOpArg, O: OutOpArg>(
+fn intcode_op_equals<D: IntcodeMemory, I1: InOpArg<D::Word>, I2: InOpArg<D::Word>, O: OutOpArg>(
     vm: &mut IntcodeVM<D>,
     val_a: I1,
     val_b: I2,
     store: O,
-) -> Result<StepResult, IntcodeError> {
+) -> Result<StepResult, IntcodeErrorKind> {
     intcode_op_conditional_store(vm, val_a, val_b, store, |a, b| a == b)
 }
 
-fn intcode_op_adjust_relative_base<D: DerefMut<Target = [i64]>, I: InOpArg>(
+fn intcode_op_adjust_relative_base<D: IntcodeMemory, I: InOpArg<D::Word>>(
     vm: &mut IntcodeVM<D>,
     adj: I,
-) -> Result<StepResult, IntcodeError> {
-    let adj_amount = adj.read(vm)?;
+) -> Result<StepResult, IntcodeErrorKind> {
+    let adj_amount = adj.read(vm)?.to_i64().ok_or(IntcodeErrorKind::Overflow)?;
 
-    vm.relative_base += adj_amount;
+    vm.relative_base = vm
+        .relative_base
+        .checked_add(adj_amount)
+        .ok_or(IntcodeErrorKind::Overflow)?;
 
     Ok(StepResult::Continue)
 }
 
-fn intcode_op_terminate<D: DerefMut<Target = [i64]>>(
+fn intcode_op_terminate<D: IntcodeMemory>(
     _vm: &IntcodeVM<D>,
-) -> Result<StepResult, IntcodeError> {
+) -> Result<StepResult, IntcodeErrorKind> {
     Ok(StepResult::Interrupt(InterruptReason::Terminate))
 }
 
-impl<D: DerefMut<Target = [i64]>> IntcodeVM<D> {
+/// Static description of an opcode: its mnemonic and how many input and output parameters follow
+/// the instruction word.
+#[derive(Debug, PartialEq)]
+pub struct OpcodeInfo {
+    pub code: i64,
+    pub mnemonic: &'static str,
+    pub inputs: usize,
+    pub outputs: usize,
+}
+
+/// Runs an opcode added with `IntcodeVM::add_opcode`. It's given the values of the
+/// instruction's inputs and fills in what to store to its outputs, which start out as zero.
+pub type OpcodeHandler<D> = fn(
+    &mut IntcodeVM<D>,
+    &[<D as IntcodeMemory>::Word],
+    &mut [<D as IntcodeMemory>::Word],
+) -> Result<StepResult, IntcodeErrorKind>;
+
+// The single opcode table. It generates `OPCODES`, which the disassembler reads, as well as
+// `IntcodeVM::execute`, which `step` dispatches through, and `IntcodeVM::decode_op`, which fills
+// the decode cache, so none of them can drift apart.
+proc::intcode_ops! {
+    1, "add", intcode_op_add, 2, 1,
+    2, "mul", intcode_op_mul, 2, 1,
+    3, "in", intcode_op_input, 0, 1,
+    4, "out", intcode_op_output, 1, 0,
+    5, "jt", intcode_op_jump_if_true, 2, 0,
+    6, "jf", intcode_op_jump_if_false, 2, 0,
+    7, "lt", intcode_op_less_than, 2, 1,
+    8, "eq", intcode_op_equals, 2, 1,
+    9, "arb", intcode_op_adjust_relative_base, 1, 0,
+    99, "hlt", intcode_op_terminate, 0, 0
+}
+
+impl<D: IntcodeMemory> IntcodeVM<D> {
     pub fn new(d: D) -> IntcodeVM<D> {
         IntcodeVM {
             ip: 0,
             data: d,
             state: VMState::Ready,
             relative_base: 0,
+            inputs: VecDeque::new(),
+            trace: None,
+            history: None,
+            profile: None,
+            cache: None,
+            loops: None,
+            opcodes: Vec::new(),
+            arithmetic: Arithmetic::default(),
         }
     }
 
-    pub fn input(&mut self, i: i64) -> Result<(), IntcodeError> {
+    /// Teaches the VM another opcode, run by `handler`. Its parameters take modes the same way
+    /// as the built in opcodes' do. Unlike `in` and `out`, an added opcode is done with once its
+    /// handler returns, so the ip moves past it even when it interrupts, unless the handler
+    /// returns `StepResult::Jump` to say it moved the ip itself. Instructions using it are never
+    /// cached, and the tracer, history and debugger decode them like any other.
+    ///
+    /// Panics if the code is already taken or isn't two digits, or if the opcode has more
+    /// parameters than any built in one.
+    pub fn add_opcode(&mut self, info: &'static OpcodeInfo, handler: OpcodeHandler<D>) {
+        assert!(
+            (0..100).contains(&info.code) && self.opcode_info(info.code).is_none(),
+            "Opcode {} is taken or out of range",
+            info.code
+        );
+        assert!(
+            info.inputs + info.outputs <= MAX_OPERANDS,
+            "Opcode {} has more than {} parameters",
+            info.code,
+            MAX_OPERANDS
+        );
+        self.opcodes.push((info, handler));
+    }
+
+    /// The built in or added opcode with `code`.
+    fn opcode_info(&self, code: i64) -> Option<&'static OpcodeInfo> {
+        disasm::opcode_info(code).or_else(|| {
+            self.opcodes
+                .iter()
+                .map(|&(info, _)| info)
+                .find(|info| info.code == code)
+        })
+    }
+
+    /// Sets what `add` and `mul` do on overflow. VMs start out with `Arithmetic::Checked`.
+    pub fn set_arithmetic(&mut self, arithmetic: Arithmetic) {
+        self.arithmetic = arithmetic;
+    }
+
+    /// Queues input for the program to consume when it next asks. Input queued this way never
+    /// causes an `InterruptReason::WaitingForInput`.
+    pub fn push_input(&mut self, i: D::Word) {
+        self.inputs.push_back(i);
+    }
+
+    pub fn input(&mut self, i: D::Word) -> Result<(), IntcodeError<D::Word>> {
         if let VMState::WaitingForInput(index) = self.state {
-            *match index {
-                SomeOutOpArg::Position(p) => p.write(self),
-                SomeOutOpArg::Relative(p) => p.write(self),
-            }? = i;
-            self.ip += 2;
-            self.state = VMState::Ready;
-            Ok(())
+            let result = self.finish_input(index, i);
+            self.fault(result)
         } else {
-            Err(IntcodeError::IllegalState)
+            Err(self.illegal_state())
+        }
+    }
+
+    fn finish_input(&mut self, index: SomeOutOpArg, i: D::Word) -> Result<(), IntcodeErrorKind> {
+        // The interrupted `in` was recorded without a write, so the write gets its own entry
+        let state = self.state.clone();
+        let mut entry = match self.is_observed() {
+            true => self.data.load(self.ip).map(|w| TraceEntry::before(self, w)),
+            false => None,
+        };
+        *match index {
+            SomeOutOpArg::Position(p) => p.write(self),
+            SomeOutOpArg::Relative(p) => p.write(self),
+        }? = i;
+        if let Some(mut entry) = entry.take() {
+            entry.after(self, &Ok(StepResult::Continue));
+            if let Some(profile) = &mut self.profile {
+                profile.record_writes(&entry);
+            }
+            self.record(entry, state, None);
         }
+        if let Some(loops) = &mut self.loops {
+            loops.reset();
+        }
+        self.ip += 2;
+        self.state = VMState::Ready;
+        Ok(())
     }
 
-    pub fn output(&mut self) -> Result<i64, IntcodeError> {
-        if let VMState::WaitingForOutput(index) = &self.state {
-            let v = match index {
-                SomeInOpArg::Position(p) => p.read(self),
-                SomeInOpArg::Immediate(p) => p.read(self),
-                SomeInOpArg::Relative(p) => p.read(self),
-            }?;
-            self.ip += 2;
-            self.state = VMState::Ready;
-            Ok(v)
+    pub fn output(&mut self) -> Result<D::Word, IntcodeError<D::Word>> {
+        if let VMState::WaitingForOutput(index) = self.state.clone() {
+            let result = self.finish_output(index);
+            self.fault(result)
         } else {
-            Err(IntcodeError::IllegalState)
+            Err(self.illegal_state())
+        }
+    }
+
+    fn finish_output(&mut self, index: SomeInOpArg<D::Word>) -> Result<D::Word, IntcodeErrorKind> {
+        let v = match index {
+            SomeInOpArg::Position(p) => p.read(self),
+            SomeInOpArg::Immediate(p) => p.read(self),
+            SomeInOpArg::Relative(p) => p.read(self),
+        }?;
+        if let Some(history) = &mut self.history {
+            history.record_move(self.ip, self.relative_base, self.state.clone());
         }
+        self.ip += 2;
+        self.state = VMState::Ready;
+        Ok(v)
+    }
+
+    /// Runs the instruction at the ip. A VM that has faulted refuses with
+    /// `IntcodeErrorKind::Faulted`, and an instruction that fails faults it.
+    pub fn step(&mut self) -> Result<StepResult, IntcodeError<D::Word>> {
+        self.check_fault()?;
+        let result = self.execute_next();
+        self.fault(result)
     }
 
-    pub fn step(&mut self) -> Result<StepResult, IntcodeError> {
-        let instruction = match self.data.get(self.ip) {
+    fn execute_next(&mut self) -> Result<StepResult, IntcodeErrorKind> {
+        if !self.is_observed() {
+            if let Some(result) = self.step_cached() {
+                return result;
+            }
+        }
+
+        let instruction = match self.data.load(self.ip) {
             Some(instruction) => instruction,
-            None => return Err(IntcodeError::OutOfBoundsIp(self.ip as i64)),
+            None => return Err(IntcodeErrorKind::OutOfBoundsIp(self.ip as i64)),
         };
 
-        proc::intcode_op!(
-            10,
-            instruction,
-            1,
-            intcode_op_add,
-            2,
-            1,
-            2,
-            intcode_op_mul,
-            2,
-            1,
-            3,
-            intcode_op_input,
-            0,
-            1,
-            4,
-            intcode_op_output,
-            1,
-            0,
-            5,
-            intcode_op_jump_if_true,
-            2,
-            0,
-            6,
-            intcode_op_jump_if_false,
-            2,
-            0,
-            7,
-            intcode_op_less_than,
-            2,
-            1,
-            8,
-            intcode_op_equals,
-            2,
-            1,
-            9,
-            intcode_op_adjust_relative_base,
-            1,
-            0,
-            99,
-            intcode_op_terminate,
-            0,
-            0
-        )
+        if !self.is_observed() {
+            return self.execute(instruction.clamp_to_i64());
+        }
+
+        let state = self.state.clone();
+        let queued = self.inputs.len();
+        let next_input = self.inputs.front().cloned();
+        let mut entry = TraceEntry::before(self, instruction.clone());
+        let result = self.execute(instruction.clamp_to_i64());
+        entry.after(self, &result);
+        let consumed = if self.inputs.len() < queued {
+            next_input
+        } else {
+            None
+        };
+        if let Some(profile) = &mut self.profile {
+            let jumped_to = match result {
+                Ok(StepResult::Jump) => Some(self.ip),
+                _ => None,
+            };
+            profile.record(&entry, jumped_to);
+        }
+        let looped = match (&mut self.loops, &result) {
+            (Some(loops), Ok(StepResult::Continue)) | (Some(loops), Ok(StepResult::Jump)) => {
+                if consumed.is_some() {
+                    loops.reset();
+                }
+                loops.record(&entry, self.ip, self.relative_base, &self.data)
+            }
+            _ => false,
+        };
+        self.record(entry, state, consumed);
+        match looped {
+            true => Ok(StepResult::Interrupt(InterruptReason::InfiniteLoop)),
+            false => result,
+        }
     }
 
-    pub fn run(&mut self) -> Result<InterruptReason, IntcodeError> {
+    /// The cell at `index`, for an instruction to write to. Anything decoded from it is
+    /// dropped from the decode cache first.
+    fn store(&mut self, index: i64) -> Result<&mut D::Word, IntcodeErrorKind> {
+        if let (Some(cache), true) = (&mut self.cache, index >= 0) {
+            cache.invalidate(index as usize);
+        }
+        read_index_mut(&mut self.data, index)
+    }
+
+    /// Whether anything wants to hear about each step, which makes stepping a good deal slower.
+    fn is_observed(&self) -> bool {
+        self.trace.is_some()
+            || self.history.is_some()
+            || self.profile.is_some()
+            || self.loops.is_some()
+    }
+
+    fn record(
+        &mut self,
+        entry: TraceEntry<D::Word>,
+        state: VMState<D::Word>,
+        consumed: Option<D::Word>,
+    ) {
+        if let Some(history) = &mut self.history {
+            history.record(&entry, state, consumed);
+        }
+        if let Some(trace) = &mut self.trace {
+            trace.record(entry);
+        }
+    }
+
+    /// Starts recording every instruction executed into `trace`, or stops tracing when given
+    /// `None`. Returns the trace that was previously in place.
+    pub fn set_trace(&mut self, trace: Option<Trace<D::Word>>) -> Option<Trace<D::Word>> {
+        std::mem::replace(&mut self.trace, trace)
+    }
+
+    pub fn trace(&self) -> Option<&Trace<D::Word>> {
+        self.trace.as_ref()
+    }
+
+    /// Starts counting what the program does into `profile`, or stops when given `None`.
+    /// Returns the profile that was previously in place.
+    pub fn set_profile(&mut self, profile: Option<Profile>) -> Option<Profile> {
+        std::mem::replace(&mut self.profile, profile)
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    pub fn run(&mut self) -> Result<InterruptReason, IntcodeError<D::Word>> {
+        self.check_fault()?;
         loop {
-            let step_result = match self.step() {
-                Ok(r) => r,
-                Err(err) => {
-                    // The VM needs to be permanently bricked
-                    self.state = VMState::Fault;
-                    return Err(err);
-                }
-            };
+            let result = self.run_cached().unwrap_or_else(|| self.execute_next());
+            if let StepResult::Interrupt(reason) = self.fault(result)? {
+                return Ok(reason);
+            }
+        }
+    }
 
-            if let StepResult::Interrupt(reason) = step_result {
+    /// Like `run`, but stops with `InterruptReason::OutOfFuel` once it has executed `*fuel`
+    /// instructions, taking the ones it does execute out of `fuel`. Whatever is left over after
+    /// an interrupt can be spent resuming the run, so a program can be given a budget for all
+    /// its work rather than for each stretch between outputs.
+    pub fn run_with_fuel(
+        &mut self,
+        fuel: &mut usize,
+    ) -> Result<InterruptReason, IntcodeError<D::Word>> {
+        self.check_fault()?;
+        while *fuel > 0 {
+            *fuel -= 1;
+            let result = self.execute_next();
+            if let StepResult::Interrupt(reason) = self.fault(result)? {
                 return Ok(reason);
             }
         }
+        Ok(InterruptReason::OutOfFuel)
+    }
+
+    /// Wraps the error from an instruction that failed, if it did. The VM is permanently
+    /// bricked, since the instruction may have been left half done.
+    fn fault<T>(
+        &mut self,
+        result: Result<T, IntcodeErrorKind>,
+    ) -> Result<T, IntcodeError<D::Word>> {
+        result.map_err(|kind| {
+            self.state = VMState::Fault;
+            self.error(kind)
+        })
+    }
+
+    fn check_fault(&self) -> Result<(), IntcodeError<D::Word>> {
+        match self.state {
+            VMState::Fault => Err(self.error(IntcodeErrorKind::Faulted)),
+            _ => Ok(()),
+        }
+    }
+
+    fn illegal_state(&self) -> IntcodeError<D::Word> {
+        match self.state {
+            VMState::Fault => self.error(IntcodeErrorKind::Faulted),
+            _ => self.error(IntcodeErrorKind::IllegalState),
+        }
+    }
+
+    /// `kind` with where the VM is and what's around it.
+    fn error(&self, kind: IntcodeErrorKind) -> IntcodeError<D::Word> {
+        let memory_start = self.ip.saturating_sub(WINDOW_BEFORE);
+        IntcodeError {
+            kind,
+            ip: self.ip,
+            instruction: self.data.load(self.ip),
+            relative_base: self.relative_base,
+            memory_start,
+            memory: (memory_start..self.ip.saturating_add(WINDOW_AFTER))
+                .map_while(|a| self.data.load(a))
+                .collect(),
+        }
+    }
+
+    /// Runs until the program terminates or needs input that isn't queued, appending everything
+    /// it outputs along the way to `outputs`.
+    pub fn run_collecting(
+        &mut self,
+        outputs: &mut Vec<D::Word>,
+    ) -> Result<InterruptReason, IntcodeError<D::Word>> {
+        loop {
+            match self.run()? {
+                InterruptReason::WaitingForOutput => outputs.push(self.output()?),
+                reason => return Ok(reason),
+            }
+        }
+    }
+
+    /// Feeds `inputs` to the program and runs it to completion, returning its output. If the
+    /// program asks for more input than given this fails with `IntcodeErrorKind::InputExhausted`,
+    /// leaving the VM waiting so more can be pushed and the run resumed. A run stopped for any
+    /// other reason before the program halts fails with `IntcodeErrorKind::Interrupted`.
+    pub fn run_with_inputs<I: IntoIterator<Item = D::Word>>(
+        &mut self,
+        inputs: I,
+    ) -> Result<Vec<D::Word>, IntcodeError<D::Word>> {
+        for i in inputs {
+            self.push_input(i);
+        }
+
+        let mut outputs = Vec::new();
+        match self.run_collecting(&mut outputs)? {
+            InterruptReason::Terminate => Ok(outputs),
+            InterruptReason::WaitingForInput => Err(self.error(IntcodeErrorKind::InputExhausted)),
+            reason => Err(self.error(IntcodeErrorKind::Interrupted(reason))),
+        }
+    }
+
+    /// Address of the next instruction to execute. While the VM is waiting for input or output
+    /// this is still the address of the instruction that is waiting.
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn relative_base(&self) -> i64 {
+        self.relative_base
+    }
+
+    pub fn state(&self) -> VMStatus {
+        match self.state {
+            VMState::Ready => VMStatus::Ready,
+            VMState::Fault => VMStatus::Fault,
+            VMState::WaitingForInput(_) => VMStatus::WaitingForInput,
+            VMState::WaitingForOutput(_) => VMStatus::WaitingForOutput,
+        }
+    }
+
+    /// Input that has been queued with `push_input` but not yet consumed.
+    pub fn queued_inputs(&self) -> impl Iterator<Item = &D::Word> {
+        self.inputs.iter()
     }
 
     pub fn data<'a>(&'a self) -> &'a D {
         return &self.data;
     }
 
+    /// Memory to change directly. Anything could change, so the decode cache and loop detector
+    /// start over.
     pub fn data_mut<'a>(&'a mut self) -> &'a mut D {
+        if let Some(cache) = &mut self.cache {
+            cache.clear();
+        }
+        if let Some(loops) = &mut self.loops {
+            loops.reset();
+        }
         return &mut self.data;
     }
 }
 
+// A program with a loop, a subroutine and I/O, for tests of everything that analyses code
+#[cfg(test)]
+const TRIANGLE: &str = include_str!("intcode/testdata/triangle.asm");
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -568,6 +1045,257 @@ mod tests {
         assert_eq!(vm.run().unwrap(), InterruptReason::Terminate);
     }
 
+    fn collect_outputs<D: IntcodeMemory<Word = i64>>(vm: &mut IntcodeVM<D>) -> Vec<i64> {
+        let mut outputs = Vec::new();
+        while vm.run().unwrap() == InterruptReason::WaitingForOutput {
+            outputs.push(vm.output().unwrap());
+        }
+        outputs
+    }
+
+    #[test]
+    fn test_intcode_input_queue() {
+        let echo_sum = vec![3, 11, 3, 12, 1, 11, 12, 13, 4, 13, 99, 0, 0, 0];
+
+        let mut vm = IntcodeVM::new(echo_sum.clone());
+        assert_eq!(vm.run_with_inputs(vec![3, 4]).unwrap(), vec![7]);
+
+        let mut vm = IntcodeVM::new(echo_sum.clone());
+        vm.push_input(3);
+        assert_eq!(vm.run().unwrap(), InterruptReason::WaitingForInput);
+        vm.input(5).unwrap();
+        assert_eq!(collect_outputs(&mut vm), vec![8]);
+
+        let mut vm = IntcodeVM::new(echo_sum);
+        assert_eq!(
+            vm.run_with_inputs(vec![1]).unwrap_err().kind,
+            IntcodeErrorKind::InputExhausted
+        );
+        assert_eq!(vm.run_with_inputs(vec![2]).unwrap(), vec![3]);
+
+        // Input pushed while waiting is taken by resuming the run, which leaves nothing waiting
+        let mut vm = IntcodeVM::new(vec![3i64, 5, 99, 0, 0, 0]);
+        assert_eq!(vm.run().unwrap(), InterruptReason::WaitingForInput);
+        vm.push_input(7);
+        assert_eq!(vm.run().unwrap(), InterruptReason::Terminate);
+        assert_eq!((vm.state(), vm.data()[5]), (VMStatus::Ready, 7));
+        assert_eq!(
+            vm.input(8).unwrap_err().kind,
+            IntcodeErrorKind::IllegalState
+        );
+
+        // A store that fails leaves the input queued
+        let mut vm = IntcodeVM::new(vec![3i64, -1, 99]);
+        vm.push_input(7);
+        assert!(vm.run().is_err());
+        assert_eq!(vm.queued_inputs().collect::<Vec<_>>(), vec![&7]);
+    }
+
+    #[test]
+    fn test_intcode_paged_memory() {
+        let quine = vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ];
+        let mut vm = IntcodeVM::new(PagedMemory::from(quine.as_slice()));
+        assert_eq!(collect_outputs(&mut vm), quine);
+
+        let mut vm = IntcodeVM::new(PagedMemory::from(vec![
+            1102, 34915192, 34915192, 7, 4, 7, 99, 0,
+        ]));
+        assert_eq!(collect_outputs(&mut vm), vec![1219070632396864]);
+
+        let mut vm = IntcodeVM::new(PagedMemory::from(vec![104, 1125899906842624, 99]));
+        assert_eq!(collect_outputs(&mut vm), vec![1125899906842624]);
+    }
+
+    #[test]
+    fn test_intcode_slice_memory_bounds() {
+        let d: Vec<i64> = vec![1101, 1, 1, 100, 99];
+        let mut vm = IntcodeVM::new(d);
+        assert_eq!(
+            vm.run().unwrap_err().kind,
+            IntcodeErrorKind::OutOfBoundsDereference(100)
+        );
+
+        let mut vm = IntcodeVM::new(PagedMemory::from(vec![1101, 1, 1, 100, 4, 100, 99]));
+        assert_eq!(collect_outputs(&mut vm), vec![2]);
+    }
+
+    #[test]
+    fn test_intcode_fault() {
+        let mut vm = IntcodeVM::new(vec![109i64, 3, 1101, 1, 1, 20, 42, 99]);
+        assert_eq!(vm.step().unwrap(), StepResult::Continue);
+        let err = vm.step().unwrap_err();
+        assert_eq!(err.kind, IntcodeErrorKind::OutOfBoundsDereference(20));
+        assert_eq!(
+            (err.ip, err.instruction, err.relative_base),
+            (2, Some(1101), 3)
+        );
+        assert_eq!(err.memory_start, 0);
+        assert_eq!(err.memory, vec![109, 3, 1101, 1, 1, 20, 42, 99]);
+        assert_eq!(
+            err.to_string(),
+            "IntcodeError: Out of bounds dereference at 20 at ip 2 (instruction 1101), \
+             relative base 3, memory from 0: 109 3 [1101] 1 1 20 42 99"
+        );
+
+        // Nothing runs on a faulted VM, however it's asked
+        assert_eq!(vm.state(), VMStatus::Fault);
+        assert_eq!(vm.step().unwrap_err().kind, IntcodeErrorKind::Faulted);
+        assert_eq!(vm.run().unwrap_err().kind, IntcodeErrorKind::Faulted);
+        assert_eq!(vm.input(1).unwrap_err().kind, IntcodeErrorKind::Faulted);
+        assert_eq!(vm.ip(), 2);
+
+        let mut vm = IntcodeVM::new(vec![104i64, 1, 42, 0]);
+        assert_eq!(
+            vm.output().unwrap_err().kind,
+            IntcodeErrorKind::IllegalState
+        );
+        assert_eq!(vm.run().unwrap(), InterruptReason::WaitingForOutput);
+        assert_eq!(vm.output().unwrap(), 1);
+        let err = vm.run().unwrap_err();
+        assert_eq!(err.kind, IntcodeErrorKind::IllegalOpcode(42));
+        assert_eq!((err.memory_start, err.memory.len()), (0, 4));
+    }
+
+    #[test]
+    fn test_intcode_fuel() {
+        // Outputs 1 forever
+        let mut vm = IntcodeVM::new(vec![104i64, 1, 1105, 1, 0]);
+        let mut fuel = 3;
+        assert_eq!(
+            vm.run_with_fuel(&mut fuel).unwrap(),
+            InterruptReason::WaitingForOutput
+        );
+        assert_eq!((fuel, vm.output().unwrap()), (2, 1));
+        assert_eq!(
+            vm.run_with_fuel(&mut fuel).unwrap(),
+            InterruptReason::WaitingForOutput
+        );
+        vm.output().unwrap();
+        assert_eq!(
+            vm.run_with_fuel(&mut fuel).unwrap(),
+            InterruptReason::OutOfFuel
+        );
+        assert_eq!((fuel, vm.ip()), (0, 2));
+
+        let mut fuel = 100;
+        let mut vm = IntcodeVM::new(vec![1101i64, 2, 3, 5, 99, 0]);
+        assert_eq!(
+            vm.run_with_fuel(&mut fuel).unwrap(),
+            InterruptReason::Terminate
+        );
+        assert_eq!(fuel, 98);
+    }
+
+    static MAX: OpcodeInfo = OpcodeInfo {
+        code: 10,
+        mnemonic: "max",
+        inputs: 2,
+        outputs: 1,
+    };
+
+    static TRAP: OpcodeInfo = OpcodeInfo {
+        code: 11,
+        mnemonic: "trap",
+        inputs: 0,
+        outputs: 0,
+    };
+
+    fn max<D: IntcodeMemory>(
+        _: &mut IntcodeVM<D>,
+        inputs: &[D::Word],
+        outputs: &mut [D::Word],
+    ) -> Result<StepResult, IntcodeErrorKind> {
+        outputs[0] = inputs[0].clone().max(inputs[1].clone());
+        Ok(StepResult::Continue)
+    }
+
+    fn trap<D: IntcodeMemory>(
+        _: &mut IntcodeVM<D>,
+        _: &[D::Word],
+        _: &mut [D::Word],
+    ) -> Result<StepResult, IntcodeErrorKind> {
+        Ok(StepResult::Interrupt(InterruptReason::Trap))
+    }
+
+    #[test]
+    fn test_intcode_custom_opcodes() {
+        // max [rb+5], #7, [30]; out [30]; trap; hlt
+        let mut program = vec![109i64, 20, 1210, 5, 7, 30, 4, 30, 11, 99];
+        program.resize(31, 0);
+        program[25] = 12;
+
+        for &cached in &[false, true] {
+            let mut vm = IntcodeVM::new(PagedMemory::from(program.clone()));
+            vm.set_decode_cache(cached);
+            vm.add_opcode(&MAX, max);
+            vm.add_opcode(&TRAP, trap);
+            assert_eq!(collect_outputs(&mut vm.fork()), vec![12]);
+            assert_eq!(vm.run().unwrap(), InterruptReason::WaitingForOutput);
+            assert_eq!(vm.output().unwrap(), 12);
+            assert_eq!(vm.run().unwrap(), InterruptReason::Trap);
+            assert_eq!(vm.ip(), 9);
+            assert_eq!(vm.run().unwrap(), InterruptReason::Terminate);
+        }
+
+        // Traced, an added opcode decodes and records its writes like any other
+        let mut vm = IntcodeVM::new(PagedMemory::from(program.clone()));
+        vm.add_opcode(&MAX, max);
+        vm.set_trace(Some(Trace::ring_buffer(8)));
+        vm.run().unwrap();
+        let entry = vm.trace().unwrap().entries().nth(1).unwrap();
+        assert_eq!(
+            entry.decoded.as_ref().unwrap().to_string(),
+            "max [rb+5], #7, [30]"
+        );
+        assert_eq!(
+            entry.writes,
+            vec![trace::MemoryWrite {
+                address: 30,
+                old: 0,
+                new: 12
+            }]
+        );
+
+        // Outputs still can't be immediate, and other VMs don't know the opcode at all
+        let mut vm = IntcodeVM::new(vec![11110i64, 1, 2, 3]);
+        vm.add_opcode(&MAX, max);
+        assert_eq!(
+            vm.run().unwrap_err().kind,
+            IntcodeErrorKind::IllegalOpcode(11110)
+        );
+        let mut vm = IntcodeVM::new(program);
+        assert_eq!(
+            vm.run().unwrap_err().kind,
+            IntcodeErrorKind::IllegalOpcode(1210)
+        );
+    }
+
+    #[test]
+    fn test_intcode_arithmetic() {
+        let add = vec![1101, i64::MAX, 1, 7, 4, 7, 99, 0];
+        let mul = vec![1102, i64::MIN, 2, 7, 4, 7, 99, 0];
+
+        let mut vm = IntcodeVM::new(PagedMemory::from(add.clone()));
+        assert_eq!(vm.run().unwrap_err().kind, IntcodeErrorKind::Overflow);
+        assert_eq!(vm.state(), VMStatus::Fault);
+        let mut vm = IntcodeVM::new(PagedMemory::from(mul.clone()));
+        assert_eq!(vm.run().unwrap_err().kind, IntcodeErrorKind::Overflow);
+
+        for (arithmetic, sum, product) in [
+            (Arithmetic::Wrapping, i64::MIN, 0),
+            (Arithmetic::Saturating, i64::MAX, i64::MIN),
+        ] {
+            let mut vm = IntcodeVM::new(PagedMemory::from(add.clone()));
+            vm.set_arithmetic(arithmetic);
+            assert_eq!(collect_outputs(&mut vm), vec![sum]);
+            let mut vm = IntcodeVM::new(PagedMemory::from(mul.clone()));
+            vm.set_arithmetic(arithmetic);
+            assert_eq!(collect_outputs(&mut vm), vec![product]);
+        }
+    }
+
     #[test]
     fn test_intcode_jump_and_cond_store() {
         run_prog(vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8], 8, 1);
@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+
+use super::OPCODES;
+use crate::consume::{Diagnostic, ParseError, ParseResult, Scanner};
+
+/// A word of output that may not be known until every label has been seen.
+enum Value<'a> {
+    Int(i64),
+    Label { name: &'a str, offset: usize },
+}
+
+struct Assembler<'a> {
+    words: Vec<(usize, Value<'a>)>,
+    labels: HashMap<&'a str, usize>,
+    line: usize,
+}
+
+/// Assembles the syntax `disassemble` prints, plus labels. Each line holds an optional address
+/// (checked against where the line actually lands), any number of `name:` label definitions and
+/// then either an instruction or a `data` directive. Operands are `[x]` for position mode, `#x`
+/// for immediates and `[rb+x]` for relative mode, where `x` outside of `[rb+x]` may be a label.
+/// Anything after a `;` is a comment.
+pub fn assemble(source: &str, text: &str) -> Result<Vec<i64>, Diagnostic> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut asm = Assembler {
+        words: Vec::new(),
+        labels: HashMap::new(),
+        line: 0,
+    };
+    for (i, line) in lines.iter().enumerate() {
+        asm.line = i;
+        let code = line.split(';').next().unwrap_or("");
+        asm.parse_line(&mut Scanner::new(code))
+            .map_err(|e| e.locate(source, line, i + 1))?;
+    }
+
+    let labels = asm.labels;
+    asm.words
+        .into_iter()
+        .map(|(line, value)| match value {
+            Value::Int(v) => Ok(v),
+            Value::Label { name, offset } => match labels.get(name) {
+                Some(&address) => Ok(address as i64),
+                None => Err(ParseError {
+                    offset,
+                    expected: format!("a defined label, found {:?}", name),
+                }
+                .locate(source, lines[line], line + 1)),
+            },
+        })
+        .collect()
+}
+
+fn is_label(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+}
+
+impl<'a> Assembler<'a> {
+    fn parse_line(&mut self, s: &mut Scanner<'a>) -> ParseResult<()> {
+        s.whitespace();
+        let start = s.offset();
+        if let Some(address) = s.optional(|s| {
+            let address = s.int::<usize>()?;
+            s.whitespace();
+            s.literal(":")?;
+            Ok(address)
+        }) {
+            if address != self.words.len() {
+                return Err(ParseError {
+                    offset: start,
+                    expected: format!("address {}, found {}", self.words.len(), address),
+                });
+            }
+        }
+
+        loop {
+            s.whitespace();
+            let start = s.offset();
+            let label = match s.optional(|s| {
+                let name = s.ident()?;
+                s.whitespace();
+                s.literal(":")?;
+                Ok(name)
+            }) {
+                Some(label) if is_label(label) => label,
+                Some(_) => {
+                    return Err(ParseError {
+                        offset: start,
+                        expected: "a label".to_owned(),
+                    })
+                }
+                None => break,
+            };
+            if self.labels.insert(label, self.words.len()).is_some() {
+                return Err(ParseError {
+                    offset: start,
+                    expected: format!("a new label, {:?} is already defined", label),
+                });
+            }
+        }
+
+        if !s.rest().trim().is_empty() {
+            self.statement(s)?;
+        }
+        s.whitespace();
+        s.end()
+    }
+
+    fn statement(&mut self, s: &mut Scanner<'a>) -> ParseResult<()> {
+        let start = s.offset();
+        let mnemonic = s.ident()?;
+        if mnemonic == "data" {
+            let values = s.separated(",", |s| {
+                s.whitespace();
+                let value = Self::value(s)?;
+                s.whitespace();
+                Ok(value)
+            })?;
+            for value in values {
+                self.emit(value);
+            }
+            return Ok(());
+        }
+
+        let opcode = match OPCODES.iter().find(|op| op.mnemonic == mnemonic) {
+            Some(opcode) => opcode,
+            None => {
+                return Err(ParseError {
+                    offset: start,
+                    expected: format!("an instruction, found {:?}", mnemonic),
+                })
+            }
+        };
+        let arity = opcode.inputs + opcode.outputs;
+        let params = if arity == 0 {
+            Vec::new()
+        } else {
+            s.separated(",", |s| {
+                s.whitespace();
+                let start = s.offset();
+                let param = Self::param(s)?;
+                s.whitespace();
+                Ok((start, param))
+            })?
+        };
+        if params.len() != arity {
+            return Err(ParseError {
+                offset: start,
+                expected: format!("{} operands for {}", arity, mnemonic),
+            });
+        }
+
+        let mut word = opcode.code;
+        let mut scale = 100;
+        for (i, (offset, (mode, _))) in params.iter().enumerate() {
+            if i >= opcode.inputs && *mode == 1 {
+                return Err(ParseError {
+                    offset: *offset,
+                    expected: "a position or relative operand to write to".to_owned(),
+                });
+            }
+            word += mode * scale;
+            scale *= 10;
+        }
+        self.emit(Value::Int(word));
+        for (_, (_, value)) in params {
+            self.emit(value);
+        }
+        Ok(())
+    }
+
+    /// An operand, returned along with its parameter mode.
+    fn param(s: &mut Scanner<'a>) -> ParseResult<(i64, Value<'a>)> {
+        if s.literal("#").is_ok() {
+            return Ok((1, Self::value(s)?));
+        }
+        if s.literal("[").is_err() {
+            return s.error("an operand");
+        }
+
+        s.whitespace();
+        let param = match s.optional(|s| {
+            s.literal("rb")?;
+            s.whitespace();
+            if !s.rest().starts_with(&['+', '-'][..]) {
+                return s.error("\"+\" or \"-\"");
+            }
+            s.int::<i64>()
+        }) {
+            Some(offset) => (2, Value::Int(offset)),
+            None => (0, Self::value(s)?),
+        };
+        s.whitespace();
+        s.literal("]")?;
+        Ok(param)
+    }
+
+    fn value(s: &mut Scanner<'a>) -> ParseResult<Value<'a>> {
+        if let Some(v) = s.optional(|s| s.int::<i64>()) {
+            return Ok(Value::Int(v));
+        }
+        let offset = s.offset();
+        match s.ident() {
+            Ok(name) if is_label(name) => Ok(Value::Label { name, offset }),
+            _ => Err(ParseError {
+                offset,
+                expected: "an integer or a label".to_owned(),
+            }),
+        }
+    }
+
+    fn emit(&mut self, value: Value<'a>) {
+        self.words.push((self.line, value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{disassemble, IntcodeVM, PagedMemory};
+
+    fn round_trip(program: &[i64]) {
+        let listing: Vec<String> = disassemble(program).iter().map(|l| l.to_string()).collect();
+        assert_eq!(
+            assemble("listing", &listing.join("\n")).unwrap(),
+            program,
+            "{}",
+            listing.join("\n")
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        round_trip(&[1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50]);
+        round_trip(&[
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ]);
+        round_trip(&[
+            11101,
+            0,
+            0,
+            0,
+            1104,
+            7,
+            3,
+            21101,
+            2,
+            3,
+            -4,
+            42,
+            i64::MIN,
+            99,
+        ]);
+        round_trip(&[204, i64::MIN, 204, i64::MAX, 104, 1125899906842624, 99]);
+    }
+
+    #[test]
+    fn test_labels() {
+        // Counts down from the input, printing every value on the way
+        let program = assemble(
+            "countdown",
+            "
+                in [counter]
+            loop:
+                out [counter]
+                add [counter], #-1, [counter]   ; step down
+                jt [counter], #loop
+                hlt
+            counter: data 0
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            program,
+            vec![3, 12, 4, 12, 1001, 12, -1, 12, 1005, 12, 2, 99, 0]
+        );
+
+        let mut vm = IntcodeVM::new(PagedMemory::from(program));
+        assert_eq!(vm.run_with_inputs(vec![3]).unwrap(), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = assemble("t.asm", "add [1], [2]").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 1));
+        assert_eq!(err.message, "expected 3 operands for add");
+
+        let err = assemble("t.asm", "hlt\n  add #1, #2, #3").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 15));
+
+        let err = assemble("t.asm", "jt #1, #nowhere").unwrap_err();
+        assert_eq!(err.message, "expected a defined label, found \"nowhere\"");
+        assert_eq!(err.column, 9);
+
+        let err = assemble("t.asm", "a: hlt\na: hlt").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 1));
+
+        let err = assemble("t.asm", "0: hlt\n2: hlt").unwrap_err();
+        assert_eq!(err.message, "expected address 1, found 2");
+
+        assert!(assemble("t.asm", "nop").is_err());
+        assert!(assemble("t.asm", "out [rb*2]").is_err());
+    }
+}
@@ -0,0 +1,187 @@
+use super::{IntcodeErrorKind, IntcodeMemory, IntcodeVM, StepResult, Word, MAX_OPERANDS};
+
+type Handler<D> = fn(
+    &mut IntcodeVM<D>,
+    &[<D as IntcodeMemory>::Word; MAX_OPERANDS],
+) -> Result<StepResult, IntcodeErrorKind>;
+
+/// An instruction decoded once: its operands as they were read from memory and a handler
+/// specialised to its parameter modes, so running it again skips fetching and dispatch.
+pub(super) struct DecodedOp<D: IntcodeMemory> {
+    pub(super) run: Handler<D>,
+    pub(super) operands: [D::Word; MAX_OPERANDS],
+    pub(super) len: usize,
+}
+
+// A derived impl would want `D: Clone`, which a function pointer doesn't need
+impl<D: IntcodeMemory> Clone for DecodedOp<D> {
+    fn clone(&self) -> Self {
+        DecodedOp {
+            run: self.run,
+            operands: self.operands.clone(),
+            len: self.len,
+        }
+    }
+}
+
+/// Decoded instructions by the address they start at. Any write to a word an entry was decoded
+/// from drops the entry, so self-modifying code is decoded afresh when it next runs.
+pub(super) struct DecodeCache<D: IntcodeMemory> {
+    ops: Vec<Option<DecodedOp<D>>>,
+    // Whether each word has been part of a cached instruction, so writes to data cost one lookup.
+    // Never unset, since instructions jumped into partway through can overlap.
+    decoded: Vec<bool>,
+}
+
+impl<D: IntcodeMemory> DecodeCache<D> {
+    fn get(&self, address: usize) -> Option<DecodedOp<D>> {
+        self.ops.get(address).cloned().flatten()
+    }
+
+    fn insert(&mut self, address: usize, op: DecodedOp<D>) {
+        let end = address + op.len;
+        if self.ops.len() < end {
+            self.ops.resize_with(end, || None);
+            self.decoded.resize(end, false);
+        }
+        self.ops[address] = Some(op);
+        for word in &mut self.decoded[address..end] {
+            *word = true;
+        }
+    }
+
+    /// Drops every entry decoded from the word at `address`.
+    #[inline]
+    pub(super) fn invalidate(&mut self, address: usize) {
+        if !self.decoded.get(address).copied().unwrap_or(false) {
+            return;
+        }
+        for start in address.saturating_sub(MAX_OPERANDS)..=address {
+            if let Some(op) = &self.ops[start] {
+                if start + op.len > address {
+                    self.ops[start] = None;
+                }
+            }
+        }
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.ops.clear();
+        self.decoded.clear();
+    }
+}
+
+impl<D: IntcodeMemory> IntcodeVM<D> {
+    /// Turns the decode cache on or off. With it on, each instruction is decoded the first time
+    /// it runs and replayed from the cache after that. That pays off for programs that spend
+    /// their time in loops and costs a little for ones that don't. Tracing, history and
+    /// profiling all bypass the cache.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.cache = match enabled {
+            true => Some(DecodeCache {
+                ops: Vec::new(),
+                decoded: Vec::new(),
+            }),
+            false => None,
+        };
+    }
+
+    /// Runs through the cache until an instruction interrupts or fails, returning what it did.
+    /// Returns `None` when the cache is off or the VM is being observed, or as soon as it comes
+    /// to an instruction it can't decode, leaving the rest to `step`.
+    pub(super) fn run_cached(&mut self) -> Option<Result<StepResult, IntcodeErrorKind>> {
+        if self.is_observed() {
+            return None;
+        }
+        loop {
+            match self.step_cached()? {
+                Ok(StepResult::Continue) | Ok(StepResult::Jump) => {}
+                result => return Some(result),
+            }
+        }
+    }
+
+    /// Runs the instruction at the ip through the cache, decoding it first if it isn't there.
+    /// Returns `None` when the cache is off or the instruction can't be decoded, leaving it to
+    /// `execute` to run or report.
+    #[inline]
+    pub(super) fn step_cached(&mut self) -> Option<Result<StepResult, IntcodeErrorKind>> {
+        let op = match self.cache.as_ref()?.get(self.ip) {
+            Some(op) => op,
+            None => self.decode_into_cache()?,
+        };
+        Some((op.run)(self, &op.operands))
+    }
+
+    #[cold]
+    fn decode_into_cache(&mut self) -> Option<DecodedOp<D>> {
+        let op = self.decode_op(self.data.load(self.ip)?.to_i64()?)?;
+        self.cache.as_mut()?.insert(self.ip, op.clone());
+        Some(op)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::{assemble, IntcodeVM, PagedMemory};
+
+    fn run_both(source: &str, inputs: Vec<i64>) -> Vec<i64> {
+        let program = assemble("test", source).unwrap();
+        let mut plain = IntcodeVM::new(PagedMemory::from(program.clone()));
+        let mut cached = IntcodeVM::new(PagedMemory::from(program));
+        cached.set_decode_cache(true);
+        let expected = plain.run_with_inputs(inputs.clone()).unwrap();
+        assert_eq!(cached.run_with_inputs(inputs).unwrap(), expected);
+        expected
+    }
+
+    #[test]
+    fn test_matches_interpreter() {
+        let outputs = run_both(
+            "
+                arb #100
+                in [rb+0]
+            loop:
+                out [rb+0]
+                add [rb+0], #-1, [rb+0]
+                jt [rb+0], #loop
+                hlt
+            ",
+            vec![4],
+        );
+        assert_eq!(outputs, vec![4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        // Counts up by rewriting the immediate operand of its own `out`
+        let outputs = run_both(
+            "
+            loop:
+                out #0
+                add [1], #1, [1]
+                lt [1], #3, [flag]
+                jt [flag], #loop
+                hlt
+            flag: data 0
+            ",
+            vec![],
+        );
+        assert_eq!(outputs, vec![0, 1, 2]);
+
+        // Switches its first instruction from position to immediate mode after one run
+        let outputs = run_both(
+            "
+                out [x]
+                add #104, #0, [0]
+                add [n], #-1, [n]
+                jt [n], #0
+                hlt
+            x:  data 42
+            n:  data 2
+            ",
+            vec![],
+        );
+        assert_eq!(outputs, vec![42, 14]);
+    }
+}
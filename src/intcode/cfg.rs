@@ -0,0 +1,234 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+use super::disasm::{decode, Instruction, Param};
+
+/// Where control can go at the end of a block. A `Jump` or `Next` can lead to an address with
+/// no block when there's no valid instruction there.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Edge {
+    /// A `jt` or `jf` with an immediate target, when it's taken.
+    Jump(usize),
+    /// Carrying on to the next instruction, including when a conditional jump isn't taken.
+    Next(usize),
+    /// A jump to an address read from memory, which could be anywhere.
+    Indirect,
+}
+
+/// A run of instructions only ever entered at the top and left at the bottom.
+pub struct Block {
+    pub start: usize,
+    pub instructions: Vec<Instruction>,
+    pub edges: Vec<Edge>,
+}
+
+impl Block {
+    pub fn halts(&self) -> bool {
+        matches!(self.instructions.last(), Some(i) if i.opcode.mnemonic == "hlt")
+    }
+}
+
+/// The control flow graph of a program, as far as it can be followed without running it.
+pub struct Cfg {
+    blocks: BTreeMap<usize, Block>,
+}
+
+/// Whether a `jt` or `jf` always jumps, as `jt #1, ...` does.
+pub(super) fn always_jumps(instruction: &Instruction) -> bool {
+    match (instruction.opcode.mnemonic, instruction.params[0]) {
+        ("jt", Param::Immediate(v)) => v != 0,
+        ("jf", Param::Immediate(v)) => v == 0,
+        _ => false,
+    }
+}
+
+/// The targets of a `jt` or `jf`: where it goes when taken, if that's known, and whether it
+/// can be taken and can fall through at all given immediate conditions.
+fn jump_edges(instruction: &Instruction) -> Vec<Edge> {
+    let next = instruction.address + instruction.size();
+    let taken = match instruction.params[1] {
+        Param::Immediate(t) if t >= 0 => Edge::Jump(t as usize),
+        _ => Edge::Indirect,
+    };
+    match instruction.params[0] {
+        Param::Immediate(_) if always_jumps(instruction) => vec![taken],
+        Param::Immediate(_) => vec![Edge::Next(next)],
+        _ => vec![taken, Edge::Next(next)],
+    }
+}
+
+pub(super) fn is_jump(instruction: &Instruction) -> bool {
+    matches!(instruction.opcode.mnemonic, "jt" | "jf")
+}
+
+impl Cfg {
+    /// Follows the program from address 0 through every jump whose target is immediate. The
+    /// instruction after an unconditional jump is followed too, since compiled code returns
+    /// there from calls, which go through memory.
+    pub fn build(program: &[i64]) -> Cfg {
+        let mut instructions = BTreeMap::new();
+        let mut leaders = BTreeSet::new();
+        leaders.insert(0);
+        let mut pending = vec![0];
+        while let Some(address) = pending.pop() {
+            if instructions.contains_key(&address) {
+                continue;
+            }
+            let instruction = match decode(program, address) {
+                Some(instruction) => instruction,
+                None => continue,
+            };
+
+            let next = address + instruction.size();
+            if is_jump(&instruction) {
+                for edge in jump_edges(&instruction) {
+                    if let Edge::Jump(target) = edge {
+                        leaders.insert(target);
+                        pending.push(target);
+                    }
+                }
+                leaders.insert(next);
+                pending.push(next);
+            } else if instruction.opcode.mnemonic != "hlt" {
+                pending.push(next);
+            }
+            instructions.insert(address, instruction);
+        }
+
+        let mut blocks = BTreeMap::new();
+        for &start in leaders.iter().filter(|a| instructions.contains_key(a)) {
+            let mut block = Block {
+                start,
+                instructions: Vec::new(),
+                edges: Vec::new(),
+            };
+            let mut address = start;
+            while let Some(instruction) = instructions.get(&address) {
+                address += instruction.size();
+                block.instructions.push(instruction.clone());
+                if is_jump(instruction) {
+                    block.edges = jump_edges(instruction);
+                    break;
+                }
+                if instruction.opcode.mnemonic == "hlt" {
+                    break;
+                }
+                if leaders.contains(&address) || !instructions.contains_key(&address) {
+                    block.edges.push(Edge::Next(address));
+                    break;
+                }
+            }
+            blocks.insert(start, block);
+        }
+        Cfg { blocks }
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+        self.blocks.values()
+    }
+
+    /// Writes the graph in Graphviz's DOT language, with a box per basic block listing its
+    /// instructions. Halting blocks get a double border, and indirect jumps all lead to a
+    /// single node standing in for anywhere.
+    pub fn write_dot<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "digraph intcode {{")?;
+        writeln!(out, "    node [shape=box, fontname=\"monospace\"];")?;
+        let mut indirect = false;
+        let mut invalid = BTreeSet::new();
+        let mut node = |address: usize| match self.blocks.contains_key(&address) {
+            true => format!("b{}", address),
+            false => {
+                invalid.insert(address);
+                format!("invalid{}", address)
+            }
+        };
+        for block in self.blocks() {
+            let mut label = String::new();
+            for instruction in &block.instructions {
+                label += &format!("{}: {}\\l", instruction.address, instruction);
+            }
+            let border = if block.halts() { ", peripheries=2" } else { "" };
+            writeln!(out, "    b{} [label=\"{}\"{}];", block.start, label, border)?;
+
+            let conditional = block.edges.len() > 1;
+            for edge in &block.edges {
+                let (to, style) = match *edge {
+                    Edge::Jump(t) if conditional => (node(t), " [color=green]"),
+                    Edge::Jump(t) => (node(t), ""),
+                    Edge::Next(n) if conditional => (node(n), " [color=red]"),
+                    Edge::Next(n) => (node(n), ""),
+                    Edge::Indirect => {
+                        indirect = true;
+                        ("indirect".to_string(), " [style=dashed]")
+                    }
+                };
+                writeln!(out, "    b{} -> {}{};", block.start, to, style)?;
+            }
+        }
+        if indirect {
+            writeln!(out, "    indirect [shape=ellipse, label=\"?\"];")?;
+        }
+        for a in invalid {
+            writeln!(
+                out,
+                "    invalid{} [shape=plaintext, label=\"{}: not an instruction\"];",
+                a, a
+            )?;
+        }
+        writeln!(out, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{assemble, TRIANGLE};
+
+    #[test]
+    fn test_blocks() {
+        let cfg = Cfg::build(&assemble("triangle.asm", TRIANGLE).unwrap());
+        let blocks: Vec<(usize, usize, &Vec<Edge>)> = cfg
+            .blocks()
+            .map(|b| (b.start, b.instructions.len(), &b.edges))
+            .collect();
+        assert_eq!(
+            blocks,
+            vec![
+                (0, 1, &vec![Edge::Next(2)]),
+                (2, 2, &vec![Edge::Jump(19), Edge::Next(7)]),
+                (7, 2, &vec![Edge::Jump(20)]),
+                (14, 2, &vec![Edge::Jump(2)]),
+                (19, 1, &vec![]),
+                (20, 1, &vec![Edge::Next(24)]),
+                (24, 3, &vec![Edge::Jump(24), Edge::Next(35)]),
+                (35, 1, &vec![Edge::Indirect]),
+            ]
+        );
+        let halts: Vec<usize> = cfg
+            .blocks()
+            .filter(|b| b.halts())
+            .map(|b| b.start)
+            .collect();
+        assert_eq!(halts, vec![19]);
+    }
+
+    #[test]
+    fn test_dot() {
+        // A conditional jump straight into data
+        let cfg = Cfg::build(&[1005, 7, 6, 99, 0, 0, 12345, 0]);
+        let mut dot = Vec::new();
+        cfg.write_dot(&mut dot).unwrap();
+        assert_eq!(
+            String::from_utf8(dot).unwrap(),
+            r#"digraph intcode {
+    node [shape=box, fontname="monospace"];
+    b0 [label="0: jt [7], #6\l"];
+    b0 -> invalid6 [color=green];
+    b0 -> b3 [color=red];
+    b3 [label="3: hlt\l", peripheries=2];
+    invalid6 [shape=plaintext, label="6: not an instruction"];
+}
+"#
+        );
+    }
+}
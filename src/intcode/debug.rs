@@ -0,0 +1,504 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+use super::disasm::decode_with;
+use super::{
+    History, IntcodeError, IntcodeErrorKind, IntcodeMemory, IntcodeVM, InterruptReason, OpcodeInfo,
+    Snapshot, StepResult, VMStatus, ZeroedMemory,
+};
+
+const HELP: &str = "\
+s [n]          step n instructions (default 1)
+c              continue until a breakpoint, watchpoint, input request or halt
+r [n]          step back n instructions (default 1)
+rw <addr>      run back to just before the cell at addr last changed
+b <addr>       toggle a breakpoint
+w <addr>       toggle a watchpoint on a memory cell
+i              show the ip, relative base, state and pending input
+x <addr> [n]   dump n memory cells (default 8, at most 4096)
+l [addr] [n]   list n instructions (default 8, at most 4096) from addr (default the ip)
+in <v>...      feed input to the program
+save <file>    save the VM's state to a file
+load <file>    restore the VM's state from a file
+q              quit
+
+A program can stop itself here with brk, opcode 98, unless the VM already has
+an opcode 98 of its own.";
+
+/// Why execution stopped before it was asked to.
+enum Stop {
+    Breakpoint(usize),
+    Watchpoint { address: usize, old: i64, new: i64 },
+    Interrupt(InterruptReason),
+    Fault(IntcodeError),
+}
+
+// How many steps the debugger remembers for stepping backwards
+const HISTORY: usize = 1 << 20;
+
+// The most cells or instructions `x` and `l` will show at once
+const MAX_COUNT: usize = 4096;
+
+static BRK: OpcodeInfo = OpcodeInfo {
+    code: 98,
+    mnemonic: "brk",
+    inputs: 0,
+    outputs: 0,
+};
+
+fn brk<D: IntcodeMemory>(
+    _: &mut IntcodeVM<D>,
+    _: &[D::Word],
+    _: &mut [D::Word],
+) -> Result<StepResult, IntcodeErrorKind> {
+    Ok(StepResult::Interrupt(InterruptReason::Trap))
+}
+
+/// Drives an `IntcodeVM` a command at a time, stopping at breakpoints on instruction addresses
+/// and whenever a watched memory cell changes value. Steps can be undone, up to a limit.
+pub struct Debugger<D: IntcodeMemory> {
+    vm: IntcodeVM<D>,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeMap<usize, i64>,
+}
+
+impl<D: IntcodeMemory<Word = i64> + Clone + ZeroedMemory> Debugger<D> {
+    pub fn new(mut vm: IntcodeVM<D>) -> Debugger<D> {
+        vm.set_history(Some(History::new(HISTORY)));
+        // A VM that already has a use for 98 keeps it and goes without brk
+        if vm.opcode_info(BRK.code).is_none() {
+            vm.add_opcode(&BRK, brk);
+        }
+        Debugger {
+            vm,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+        }
+    }
+
+    fn load(&self, address: usize) -> i64 {
+        self.vm.data().load(address).unwrap_or(0)
+    }
+
+    /// Prints the instruction at `address`, or the raw word if it doesn't decode.
+    fn list_one<W: Write>(&self, out: &mut W, address: usize) -> io::Result<usize> {
+        let words: Vec<i64> = (address..address + 4)
+            .map_while(|a| self.vm.data().load(a))
+            .collect();
+        let marker = if address == self.vm.ip() { "=>" } else { "  " };
+        match decode_with(&words, 0, |code| self.vm.opcode_info(code)) {
+            Some(instruction) => {
+                writeln!(out, "{} {:>5}: {}", marker, address, instruction)?;
+                Ok(instruction.size())
+            }
+            None => {
+                let word = words.first().map_or("?".to_owned(), |w| w.to_string());
+                writeln!(out, "{} {:>5}: data {}", marker, address, word)?;
+                Ok(1)
+            }
+        }
+    }
+
+    pub fn show_location<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        self.list_one(out, self.vm.ip()).map(|_| ())
+    }
+
+    fn step_once<W: Write>(&mut self, out: &mut W) -> io::Result<Option<Stop>> {
+        match self.vm.step() {
+            Err(e) => return Ok(Some(Stop::Fault(e))),
+            Ok(StepResult::Interrupt(InterruptReason::WaitingForOutput)) => {
+                match self.vm.output() {
+                    Ok(v) => writeln!(out, "output: {}", v)?,
+                    Err(e) => return Ok(Some(Stop::Fault(e))),
+                }
+            }
+            Ok(StepResult::Interrupt(reason)) => return Ok(Some(Stop::Interrupt(reason))),
+            Ok(_) => {}
+        }
+        Ok(self.check_watchpoints())
+    }
+
+    fn check_watchpoints(&mut self) -> Option<Stop> {
+        let vm = &self.vm;
+        for (&address, last) in self.watchpoints.iter_mut() {
+            let new = vm.data().load(address).unwrap_or(0);
+            if new != *last {
+                let old = std::mem::replace(last, new);
+                return Some(Stop::Watchpoint { address, old, new });
+            }
+        }
+        None
+    }
+
+    fn report<W: Write>(&self, out: &mut W, stop: Stop) -> io::Result<()> {
+        match stop {
+            Stop::Breakpoint(address) => writeln!(out, "breakpoint at {}", address)?,
+            Stop::Watchpoint { address, old, new } => {
+                writeln!(out, "watchpoint: [{}] {} -> {}", address, old, new)?
+            }
+            Stop::Interrupt(InterruptReason::WaitingForInput) => {
+                writeln!(out, "waiting for input")?
+            }
+            Stop::Interrupt(InterruptReason::Terminate) => writeln!(out, "halted")?,
+            Stop::Interrupt(InterruptReason::Trap) => writeln!(out, "stopped by brk")?,
+            Stop::Interrupt(reason) => writeln!(out, "interrupted: {:?}", reason)?,
+            Stop::Fault(e) => writeln!(out, "fault: {}", e)?,
+        }
+        Ok(())
+    }
+
+    fn step<W: Write>(&mut self, out: &mut W, count: usize) -> io::Result<()> {
+        for _ in 0..count {
+            if let Some(stop) = self.step_once(out)? {
+                self.report(out, stop)?;
+                break;
+            }
+        }
+        self.show_location(out)
+    }
+
+    fn cont<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        let stop = loop {
+            if let Some(stop) = self.step_once(out)? {
+                break stop;
+            }
+            if self.breakpoints.contains(&self.vm.ip()) {
+                break Stop::Breakpoint(self.vm.ip());
+            }
+        };
+        self.report(out, stop)?;
+        self.show_location(out)
+    }
+
+    fn info<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let queued: Vec<String> = self.vm.queued_inputs().map(|i| i.to_string()).collect();
+        writeln!(out, "ip: {}", self.vm.ip())?;
+        writeln!(out, "relative base: {}", self.vm.relative_base())?;
+        writeln!(out, "state: {:?}", self.vm.state())?;
+        writeln!(out, "queued input: [{}]", queued.join(", "))?;
+        match self.vm.history() {
+            Some(history) if !history.is_empty() => {
+                writeln!(out, "history: {} steps", history.len())
+            }
+            _ => writeln!(out, "history: none"),
+        }
+    }
+
+    fn refresh_watchpoints(&mut self) {
+        for (&address, last) in self.watchpoints.iter_mut() {
+            *last = self.vm.data().load(address).unwrap_or(0);
+        }
+    }
+
+    fn back<W: Write>(&mut self, out: &mut W, count: usize) -> io::Result<()> {
+        for _ in 0..count {
+            if !self.vm.step_back() {
+                writeln!(out, "no more history")?;
+                break;
+            }
+        }
+        self.refresh_watchpoints();
+        self.show_location(out)
+    }
+
+    fn back_to_change<W: Write>(&mut self, out: &mut W, address: usize) -> io::Result<()> {
+        if self.vm.run_back_to_change(address) {
+            writeln!(out, "[{}] last changed here", address)?;
+        } else {
+            writeln!(out, "[{}] didn't change within the history", address)?;
+        }
+        self.refresh_watchpoints();
+        self.show_location(out)
+    }
+
+    fn input<W: Write>(&mut self, out: &mut W, values: Vec<i64>) -> io::Result<()> {
+        let mut values = values.into_iter();
+        // A pending `in` takes the first value straight away; the rest wait in the queue
+        if self.vm.state() == VMStatus::WaitingForInput {
+            if let Some(v) = values.next() {
+                if let Err(e) = self.vm.input(v) {
+                    return self.report(out, Stop::Fault(e));
+                }
+                if let Some(stop) = self.check_watchpoints() {
+                    self.report(out, stop)?;
+                }
+            }
+        }
+        for v in values {
+            self.vm.push_input(v);
+        }
+        Ok(())
+    }
+
+    fn file_command<W: Write>(&mut self, out: &mut W, command: &str, path: &str) -> io::Result<()> {
+        if command == "save" {
+            match self.vm.snapshot().save(path) {
+                Ok(()) => writeln!(out, "saved to {}", path),
+                Err(e) => writeln!(out, "couldn't save to {}: {}", path, e),
+            }
+        } else {
+            match Snapshot::load(path) {
+                Ok(snapshot) => {
+                    self.vm.restore(&snapshot);
+                    self.refresh_watchpoints();
+                    self.show_location(out)
+                }
+                Err(e) => writeln!(out, "couldn't load {}:\n{}", path, e),
+            }
+        }
+    }
+
+    /// Runs one command line, writing whatever it prints to `out`. Returns false once the user
+    /// has asked to quit.
+    pub fn command<W: Write>(&mut self, out: &mut W, line: &str) -> io::Result<bool> {
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => return Ok(true),
+        };
+        if let ("save", Some(path)) | ("load", Some(path)) = (command, words.clone().next()) {
+            return self.file_command(out, command, path).map(|_| true);
+        }
+        let args: Result<Vec<i64>, _> = words.map(str::parse::<i64>).collect();
+        let args = match args {
+            Ok(args) => args,
+            Err(e) => {
+                writeln!(out, "bad argument: {}", e)?;
+                return Ok(true);
+            }
+        };
+        // Everything but input is an address or a count
+        if let (false, Some(a)) = (command == "in", args.iter().find(|&&a| a < 0)) {
+            writeln!(out, "bad argument: {} is negative", a)?;
+            return Ok(true);
+        }
+        let address = |i: usize| args.get(i).map(|&a| a as usize);
+        let count = address(1).unwrap_or(8);
+        if matches!(command, "x" | "l" | "list") && count > MAX_COUNT {
+            writeln!(out, "bad argument: {} is more than {}", count, MAX_COUNT)?;
+            return Ok(true);
+        }
+
+        match (command, address(0)) {
+            ("q", _) | ("quit", _) => return Ok(false),
+            ("s", n) | ("step", n) => self.step(out, n.unwrap_or(1))?,
+            ("c", _) | ("continue", _) => self.cont(out)?,
+            ("r", n) | ("back", n) => self.back(out, n.unwrap_or(1))?,
+            ("rw", Some(a)) => self.back_to_change(out, a)?,
+            ("b", Some(a)) | ("break", Some(a)) => {
+                if self.breakpoints.insert(a) {
+                    writeln!(out, "breakpoint set at {}", a)?;
+                } else {
+                    self.breakpoints.remove(&a);
+                    writeln!(out, "breakpoint cleared at {}", a)?;
+                }
+            }
+            ("w", Some(a)) | ("watch", Some(a)) => {
+                if self.watchpoints.remove(&a).is_some() {
+                    writeln!(out, "watchpoint cleared at {}", a)?;
+                } else {
+                    self.watchpoints.insert(a, self.load(a));
+                    writeln!(out, "watching [{}] = {}", a, self.load(a))?;
+                }
+            }
+            ("i", _) | ("info", _) => self.info(out)?,
+            ("x", Some(a)) => {
+                let values: Vec<String> = (a..a.saturating_add(count))
+                    .map(|a| self.load(a).to_string())
+                    .collect();
+                writeln!(out, "{:>5}: {}", a, values.join(", "))?;
+            }
+            ("l", a) | ("list", a) => {
+                let mut a = a.unwrap_or_else(|| self.vm.ip());
+                for _ in 0..count {
+                    a = a.saturating_add(self.list_one(out, a)?);
+                }
+            }
+            ("in", Some(_)) => self.input(out, args)?,
+            _ => writeln!(out, "{}", HELP)?,
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{assemble, PagedMemory};
+
+    fn session(source: &str, commands: &[&str]) -> String {
+        let program = assemble("test", source).unwrap();
+        let mut debugger = Debugger::new(IntcodeVM::new(PagedMemory::from(program)));
+        let mut out = Vec::new();
+        for command in commands {
+            debugger.command(&mut out, command).unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    const DOUBLER: &str = "
+            in [value]
+        loop:
+            add [value], [value], [value]
+            out [value]
+            jt #1, #loop
+        value: data 0
+    ";
+
+    #[test]
+    fn test_breakpoints_and_input() {
+        let out = session(DOUBLER, &["c", "in 3", "b 6", "c", "c", "i"]);
+        assert_eq!(
+            out,
+            "\
+waiting for input
+=>     0: in [11]
+breakpoint set at 6
+breakpoint at 6
+=>     6: out [11]
+output: 6
+breakpoint at 6
+=>     6: out [11]
+ip: 6
+relative base: 0
+state: Ready
+queued input: []
+history: 7 steps
+"
+        );
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join("aoc_intcode_debug_test.snapshot");
+        let path = path.to_str().unwrap();
+        let save = format!("save {}", path);
+        let load = format!("load {}", path);
+        let echo = "
+            loop:
+                in [value]
+                add [value], [value], [value]
+                out [value]
+                jt #1, #loop
+            value: data 0
+        ";
+        let out = session(echo, &["in 1", "s 3", &save, "c", &load, "in 3", "s 4"]);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(
+            out.replace(path, "FILE"),
+            "\
+output: 2
+=>     8: jt #1, #0
+saved to FILE
+waiting for input
+=>     0: in [11]
+=>     8: jt #1, #0
+output: 6
+=>     8: jt #1, #0
+"
+        );
+    }
+
+    #[test]
+    fn test_reverse() {
+        let out = session(DOUBLER, &["in 3", "s 9", "rw 11", "r 2", "i", "r"]);
+        assert_eq!(
+            out,
+            "\
+output: 6
+output: 12
+output: 24
+=>     8: jt #1, #2
+[11] last changed here
+=>     2: add [11], [11], [11]
+=>     6: out [11]
+ip: 6
+relative base: 0
+state: WaitingForOutput
+queued input: []
+history: 7 steps
+=>     6: out [11]
+"
+        );
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let out = session(DOUBLER, &["w 11", "in 5", "c", "s 2", "x 10 2"]);
+        assert_eq!(
+            out,
+            "\
+watching [11] = 0
+watchpoint: [11] 0 -> 5
+=>     2: add [11], [11], [11]
+watchpoint: [11] 5 -> 10
+=>     6: out [11]
+   10: 2, 10
+"
+        );
+    }
+
+    #[test]
+    fn test_bad_arguments() {
+        // Addresses and counts can't be negative, nor counts too big to show, but input can be
+        // anything
+        let out = session(
+            DOUBLER,
+            &[
+                "x -1",
+                "b -4",
+                "l 0 -1",
+                "x 9223372036854775806 2",
+                "x 0 1000000000000",
+                "l 0 4097",
+                "in -3",
+                "s 3",
+            ],
+        );
+        assert_eq!(
+            out,
+            "\
+bad argument: -1 is negative
+bad argument: -4 is negative
+bad argument: -1 is negative
+9223372036854775806: 0, 0
+bad argument: 1000000000000 is more than 4096
+bad argument: 4097 is more than 4096
+output: -6
+=>     8: jt #1, #2
+"
+        );
+    }
+
+    #[test]
+    fn test_opcode_98_taken() {
+        static SKIP: OpcodeInfo = OpcodeInfo {
+            code: 98,
+            mnemonic: "skip",
+            inputs: 0,
+            outputs: 0,
+        };
+        fn skip<D: IntcodeMemory>(
+            _: &mut IntcodeVM<D>,
+            _: &[D::Word],
+            _: &mut [D::Word],
+        ) -> Result<StepResult, IntcodeErrorKind> {
+            Ok(StepResult::Continue)
+        }
+
+        let mut vm = IntcodeVM::new(PagedMemory::from(vec![98, 99]));
+        vm.add_opcode(&SKIP, skip);
+        let mut debugger = Debugger::new(vm);
+        let mut out = Vec::new();
+        debugger.command(&mut out, "s").unwrap();
+        debugger.command(&mut out, "c").unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\
+=>     1: hlt
+halted
+=>     1: hlt
+"
+        );
+    }
+}
@@ -0,0 +1,558 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+
+use super::cfg::{Block, Cfg, Edge};
+use super::disasm::{Instruction, Param};
+
+/// Cells at a fixed address are globals named after it; cells off the relative base are
+/// indexed off `rb`, which compiled code uses as its stack frame.
+fn operand(param: Param) -> String {
+    match param {
+        Param::Immediate(v) => v.to_string(),
+        Param::Position(a) => format!("v{}", a),
+        Param::Relative(o) => format!("rb[{}]", o),
+    }
+}
+
+fn arithmetic(instruction: &Instruction) -> String {
+    let (a, b) = (instruction.params[0], instruction.params[1]);
+    match (instruction.opcode.mnemonic, a, b) {
+        ("add", Param::Immediate(0), x) | ("add", x, Param::Immediate(0)) => operand(x),
+        ("add", x, Param::Immediate(n)) if n < 0 && n != i64::MIN => {
+            format!("{} - {}", operand(x), -n)
+        }
+        ("mul", Param::Immediate(1), x) | ("mul", x, Param::Immediate(1)) => operand(x),
+        ("mul", Param::Immediate(-1), x) | ("mul", x, Param::Immediate(-1)) => {
+            format!("-{}", operand(x))
+        }
+        ("add", _, _) => format!("{} + {}", operand(a), operand(b)),
+        ("mul", _, _) => format!("{} * {}", operand(a), operand(b)),
+        ("lt", _, _) => format!("{} < {}", operand(a), operand(b)),
+        _ => format!("{} == {}", operand(a), operand(b)),
+    }
+}
+
+/// The opposite of a condition made by `arithmetic` or a plain truth test.
+fn negate(instruction: &Instruction) -> String {
+    let (a, b) = (
+        operand(instruction.params[0]),
+        operand(instruction.params[1]),
+    );
+    match instruction.opcode.mnemonic {
+        "lt" => format!("{} >= {}", a, b),
+        _ => format!("{} != {}", a, b),
+    }
+}
+
+fn statement(instruction: &Instruction) -> String {
+    let params = &instruction.params;
+    match instruction.opcode.mnemonic {
+        "add" | "mul" | "lt" | "eq" => {
+            format!("{} = {};", operand(params[2]), arithmetic(instruction))
+        }
+        "in" => format!("{} = input();", operand(params[0])),
+        "out" => format!("output({});", operand(params[0])),
+        "arb" => match params[0] {
+            Param::Immediate(n) if n < 0 && n != i64::MIN => format!("rb -= {};", -n),
+            p => format!("rb += {};", operand(p)),
+        },
+        _ => "halt();".to_string(),
+    }
+}
+
+#[derive(Clone)]
+enum Exit {
+    /// Carries on at this address, as if falling through.
+    Next(usize),
+    /// Jumps to the target, or when there's a condition, only if it holds and otherwise
+    /// carries on at the fall through address.
+    Jump {
+        condition: Option<String>,
+        negated: Option<String>,
+        target: Result<usize, String>,
+        next: Option<usize>,
+    },
+    /// A call to the function at `target` that comes back to `next`.
+    Call {
+        target: usize,
+        next: usize,
+    },
+    /// An unconditional jump back to an address kept on the relative base stack.
+    Return,
+    Halt,
+}
+
+/// A basic block turned into statements and the way it's left.
+#[derive(Clone)]
+struct Decompiled {
+    start: usize,
+    statements: Vec<String>,
+    exit: Exit,
+}
+
+impl Decompiled {
+    fn successors(&self) -> Vec<usize> {
+        match &self.exit {
+            Exit::Next(n) => vec![*n],
+            Exit::Jump { target, next, .. } => {
+                target.iter().copied().chain(next.iter().copied()).collect()
+            }
+            Exit::Call { next, .. } => vec![*next],
+            Exit::Return | Exit::Halt => vec![],
+        }
+    }
+}
+
+/// Recognises a block ending `add #ret, #0, [rb+n]` ... `jt #1, #function`, where `ret` is the
+/// address just after the jump, as a call. Returns the index of the instruction storing the
+/// return address.
+fn find_call(block: &Block) -> Option<usize> {
+    let jump = block.instructions.last()?;
+    let next = (jump.address + jump.size()) as i64;
+    if block.edges.len() != 1 || !matches!(block.edges[0], Edge::Jump(_)) {
+        return None;
+    }
+    block.instructions.iter().position(|i| {
+        matches!(i.opcode.mnemonic, "add" | "mul")
+            && matches!(i.params[2], Param::Relative(_))
+            && arithmetic(i) == next.to_string()
+    })
+}
+
+fn decompile_block(block: &Block, reads: &HashMap<Param, usize>) -> Decompiled {
+    let mut instructions: Vec<&Instruction> = block.instructions.iter().collect();
+    let last = match instructions.last() {
+        Some(last) => *last,
+        None => {
+            return Decompiled {
+                start: block.start,
+                statements: Vec::new(),
+                exit: Exit::Next(block.start),
+            }
+        }
+    };
+    let next = last.address + last.size();
+    let mut kept = Vec::new();
+
+    let exit = if let Some(store) = find_call(block) {
+        instructions.remove(store);
+        instructions.pop();
+        match block.edges[0] {
+            Edge::Jump(target) => Exit::Call { target, next },
+            _ => unreachable!("calls are direct jumps"),
+        }
+    } else if last.opcode.mnemonic == "hlt" {
+        instructions.pop();
+        Exit::Halt
+    } else if matches!(last.opcode.mnemonic, "jt" | "jf") {
+        instructions.pop();
+        let jumps_on_zero = last.opcode.mnemonic == "jf";
+        let tested = last.params[0];
+        let target = match last.params[1] {
+            Param::Immediate(t) if t >= 0 => Ok(t as usize),
+            p => Err(operand(p)),
+        };
+        // A comparison whose only use is this jump becomes its condition. One whose result is
+        // read again is worked out into a temporary named after it, stored and then tested.
+        let compared = match instructions.last() {
+            Some(i) if matches!(i.opcode.mnemonic, "lt" | "eq") && i.params[2] == tested => {
+                Some(*i)
+            }
+            _ => None,
+        };
+        let (is_true, is_false) = match compared {
+            Some(c) if reads.get(&tested) == Some(&1) => {
+                instructions.pop();
+                (arithmetic(c), negate(c))
+            }
+            Some(c) => {
+                instructions.pop();
+                let temporary = format!("t{}", c.address);
+                kept.push(format!("{} = {};", temporary, arithmetic(c)));
+                kept.push(format!("{} = {};", operand(tested), temporary));
+                (format!("{} != 0", temporary), format!("{} == 0", temporary))
+            }
+            None => (
+                format!("{} != 0", operand(tested)),
+                format!("{} == 0", operand(tested)),
+            ),
+        };
+        let (condition, negated) = match jumps_on_zero {
+            false => (is_true, is_false),
+            true => (is_false, is_true),
+        };
+        match (tested, &target) {
+            (Param::Immediate(v), _) if (v == 0) == jumps_on_zero => match target {
+                Err(_) if matches!(last.params[1], Param::Relative(_)) => Exit::Return,
+                _ => Exit::Jump {
+                    condition: None,
+                    negated: None,
+                    target,
+                    next: None,
+                },
+            },
+            (Param::Immediate(_), _) => Exit::Next(next),
+            _ => Exit::Jump {
+                condition: Some(condition),
+                negated: Some(negated),
+                target,
+                next: Some(next),
+            },
+        }
+    } else {
+        Exit::Next(next)
+    };
+
+    let mut statements: Vec<String> = instructions.into_iter().map(statement).collect();
+    statements.extend(kept);
+    Decompiled {
+        start: block.start,
+        statements,
+        exit,
+    }
+}
+
+/// Blocks `first..=last` of a function, by index, to be printed as a loop or an `if`.
+#[derive(Clone, Copy, PartialEq)]
+struct Range {
+    first: usize,
+    last: usize,
+}
+
+impl Range {
+    fn nests_with(&self, other: &Range) -> bool {
+        self.last < other.first
+            || other.last < self.first
+            || (self.first <= other.first && other.last <= self.last)
+            || (other.first <= self.first && self.last <= other.last)
+    }
+}
+
+/// Whether control only enters blocks `first..=last` at the top, apart from jumps from `from`.
+fn single_entry(blocks: &[Decompiled], range: Range, from: Option<usize>) -> bool {
+    let inside: BTreeSet<usize> = blocks[range.first + 1..=range.last]
+        .iter()
+        .map(|b| b.start)
+        .collect();
+    blocks.iter().enumerate().all(|(i, block)| {
+        (range.first <= i && i <= range.last)
+            || Some(i) == from
+            || block.successors().iter().all(|s| !inside.contains(s))
+    })
+}
+
+fn function_name(address: usize) -> String {
+    match address {
+        0 => "main".to_string(),
+        a => format!("f{}", a),
+    }
+}
+
+fn write_function(out: &mut String, entry: usize, blocks: &[Decompiled]) {
+    let index: BTreeMap<usize, usize> = blocks
+        .iter()
+        .enumerate()
+        .map(|(i, b)| (b.start, i))
+        .collect();
+
+    // Back edges become loops and forward conditional jumps over blocks become `if`s, as long
+    // as they nest and nothing jumps into the middle of them
+    let mut loops = BTreeMap::new();
+    let mut ifs = BTreeMap::new();
+    let mut ranges: Vec<Range> = Vec::new();
+    let mut candidates = Vec::new();
+    for (i, block) in blocks.iter().enumerate() {
+        if let Exit::Jump {
+            target: Ok(target), ..
+        } = &block.exit
+        {
+            match index.get(target) {
+                Some(&j) if j <= i => candidates.push((true, Range { first: j, last: i })),
+                Some(&k) if k > i + 1 && block.successors().len() == 2 => candidates.push((
+                    false,
+                    Range {
+                        first: i + 1,
+                        last: k - 1,
+                    },
+                )),
+                _ => {}
+            }
+        }
+    }
+    candidates.sort_by_key(|(_, r)| std::cmp::Reverse(r.last - r.first));
+    for (is_loop, range) in candidates {
+        let entered_from = if is_loop { None } else { Some(range.first - 1) };
+        let closes_loop = |r: &Range| loops.values().any(|l: &Range| l.last == r.last);
+        if !ranges.iter().all(|r| r.nests_with(&range))
+            || !single_entry(blocks, range, entered_from)
+            || (!is_loop && (closes_loop(&range) || ifs.contains_key(&range.first)))
+            || (is_loop && loops.contains_key(&range.last))
+        {
+            continue;
+        }
+        ranges.push(range);
+        match is_loop {
+            true => loops.insert(range.last, range),
+            false => ifs.insert(range.first, range),
+        };
+    }
+
+    let mut labels = BTreeSet::new();
+    let goto = |labels: &mut BTreeSet<usize>, target: usize| {
+        labels.insert(target);
+        format!("goto L{};", target)
+    };
+    // Lines by indentation, with labels as `None` until it's known which ones are jumped to
+    let mut body: Vec<(Option<usize>, String)> = Vec::new();
+    let mut depth = 1;
+    for (i, block) in blocks.iter().enumerate() {
+        let opens: Vec<&Range> = loops.values().filter(|l| l.first == i).collect();
+        body.push((None, block.start.to_string()));
+        for l in opens {
+            let opening = match &blocks[l.last].exit {
+                Exit::Jump {
+                    condition: Some(_), ..
+                } => "do {",
+                _ => "while (1) {",
+            };
+            body.push((Some(depth), opening.to_string()));
+            depth += 1;
+        }
+        for s in &block.statements {
+            body.push((Some(depth), s.clone()));
+        }
+
+        let following = blocks.get(i + 1).map(|b| b.start);
+        let mut falls_to = None;
+        match &block.exit {
+            Exit::Next(n) => falls_to = Some(*n),
+            Exit::Halt => body.push((Some(depth), "halt();".to_string())),
+            Exit::Return => body.push((Some(depth), "return;".to_string())),
+            Exit::Call { target, next } => {
+                body.push((Some(depth), format!("{}();", function_name(*target))));
+                falls_to = Some(*next);
+            }
+            Exit::Jump {
+                condition,
+                negated,
+                target,
+                next,
+            } => {
+                falls_to = *next;
+                if loops.contains_key(&i) {
+                    depth -= 1;
+                    body.push(match condition {
+                        Some(c) => (Some(depth), format!("}} while ({});", c)),
+                        None => (Some(depth), "}".to_string()),
+                    });
+                } else if ifs.contains_key(&(i + 1)) {
+                    let negated = negated.as_deref().unwrap_or("1");
+                    body.push((Some(depth), format!("if ({}) {{", negated)));
+                    depth += 1;
+                } else {
+                    let jump = match target {
+                        Ok(t) => goto(&mut labels, *t),
+                        Err(address) => format!("goto *{};", address),
+                    };
+                    body.push(match condition {
+                        Some(c) => (Some(depth), format!("if ({}) {}", c, jump)),
+                        None => (Some(depth), jump),
+                    });
+                }
+            }
+        }
+        if let Some(n) = falls_to {
+            if Some(n) != following {
+                body.push((Some(depth), goto(&mut labels, n)));
+            }
+        }
+        if ifs.values().any(|r| r.last == i) {
+            for _ in ifs.values().filter(|r| r.last == i) {
+                depth -= 1;
+                body.push((Some(depth), "}".to_string()));
+            }
+        }
+    }
+
+    let _ = writeln!(out, "void {}() {{", function_name(entry));
+    for (depth, line) in body {
+        match depth {
+            Some(depth) => {
+                let _ = writeln!(out, "{}{}", "    ".repeat(depth), line);
+            }
+            None if labels.contains(&line.parse().unwrap_or(usize::MAX)) => {
+                let _ = writeln!(out, "L{}:", line);
+            }
+            None => {}
+        }
+    }
+    let _ = writeln!(out, "}}");
+}
+
+/// Turns `program` into C-like pseudocode. Code is found by following the program from its
+/// entry point; subroutines called through the relative base stack, loops, `if`s and
+/// comparisons feeding jumps are recognised and printed as such, with `goto` for anything that
+/// doesn't fit. Globals are named after their address and stack slots are written `rb[n]`;
+/// comparisons that are tested but also read again go through a temporary `tN`, named after
+/// the comparison's address.
+pub fn decompile(program: &[i64]) -> String {
+    let cfg = Cfg::build(program);
+    let mut reads = HashMap::new();
+    for instruction in cfg.blocks().flat_map(|b| b.instructions.iter()) {
+        for &param in &instruction.params[..instruction.opcode.inputs] {
+            if !matches!(param, Param::Immediate(_)) {
+                *reads.entry(param).or_insert(0) += 1;
+            }
+        }
+    }
+    let decompiled: BTreeMap<usize, Decompiled> = cfg
+        .blocks()
+        .map(|b| (b.start, decompile_block(b, &reads)))
+        .collect();
+
+    // Each function gets the blocks it reaches first, treating calls as coming back
+    let mut owned = BTreeSet::new();
+    let mut functions = vec![0];
+    let mut out = String::new();
+    let mut f = 0;
+    while f < functions.len() {
+        let entry = functions[f];
+        f += 1;
+        let mut body = BTreeSet::new();
+        let mut pending = vec![entry];
+        while let Some(address) = pending.pop() {
+            let block = match decompiled.get(&address) {
+                Some(block) if !owned.contains(&address) => block,
+                _ => continue,
+            };
+            owned.insert(address);
+            body.insert(address);
+            if let Exit::Call { target, .. } = block.exit {
+                if !functions.contains(&target) {
+                    functions.push(target);
+                }
+            }
+            pending.extend(block.successors());
+        }
+        if body.is_empty() {
+            continue;
+        }
+
+        let blocks: Vec<Decompiled> = body
+            .into_iter()
+            .filter_map(|a| decompiled.get(&a).cloned())
+            .collect();
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        write_function(&mut out, entry, &blocks);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{assemble, TRIANGLE};
+
+    #[test]
+    fn test_decompile() {
+        let program = assemble("triangle.asm", TRIANGLE).unwrap();
+        assert_eq!(
+            decompile(&program),
+            "\
+void main() {
+    rb += 100;
+    while (1) {
+        rb[1] = input();
+        if (rb[1] == 0) goto L19;
+        f20();
+        output(rb[2]);
+    }
+L19:
+    halt();
+}
+
+void f20() {
+    rb[2] = 0;
+    do {
+        rb[2] = rb[2] + rb[1];
+        rb[1] = rb[1] - 1;
+    } while (rb[1] != 0);
+    return;
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_conditions() {
+        let program = assemble(
+            "max",
+            "
+                in [a]
+                in [b]
+                lt [a], [b], [t]
+                jf [t], #skip
+                add [b], #0, [a]
+            skip:
+                out [a]
+                hlt
+            a: data 0
+            b: data 0
+            t: data 0
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            decompile(&program),
+            "\
+void main() {
+    v18 = input();
+    v19 = input();
+    if (v18 < v19) {
+        v18 = v19;
+    }
+    output(v18);
+    halt();
+}
+"
+        );
+
+        // A result that's read again, on the stack or over one of its own operands, is kept
+        let program = assemble(
+            "clamp",
+            "
+                in [rb+0]
+                lt [rb+0], #10, [rb+1]
+                jt [rb+1], #small
+                eq [rb+0], #10, [rb+0]
+                jf [rb+0], #small
+                out [rb+0]
+            small:
+                out [rb+1]
+                hlt
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            decompile(&program),
+            "\
+void main() {
+    rb[0] = input();
+    t2 = rb[0] < 10;
+    rb[1] = t2;
+    if (t2 == 0) {
+        t9 = rb[0] == 10;
+        rb[0] = t9;
+        if (t9 != 0) {
+            output(rb[0]);
+        }
+    }
+    output(rb[1]);
+    halt();
+}
+"
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use std::fmt;
+
+use super::{OpcodeInfo, OPCODES};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Param {
+    Position(i64),
+    Immediate(i64),
+    Relative(i64),
+}
+
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Param::Position(a) => write!(f, "[{}]", a),
+            Param::Immediate(a) => write!(f, "#{}", a),
+            Param::Relative(a) => write!(f, "[rb{:+}]", a),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Instruction {
+    pub address: usize,
+    pub opcode: &'static OpcodeInfo,
+    pub params: Vec<Param>,
+}
+
+impl Instruction {
+    /// Number of words the instruction occupies, including the instruction word itself.
+    pub fn size(&self) -> usize {
+        1 + self.params.len()
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.opcode.mnemonic)?;
+        for (i, param) in self.params.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { ", " }, param)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn opcode_info(code: i64) -> Option<&'static OpcodeInfo> {
+    OPCODES.iter().find(|op| op.code == code)
+}
+
+/// Decodes the instruction at `address`, or returns `None` if the words there aren't one the VM
+/// would accept: an unknown opcode, a mode the parameter can't take, or parameters running off
+/// the end of the program.
+pub fn decode(program: &[i64], address: usize) -> Option<Instruction> {
+    decode_with(program, address, opcode_info)
+}
+
+/// Like `decode`, but looks opcodes up with `opcodes`, for a VM that has had opcodes added.
+pub fn decode_with<F>(program: &[i64], address: usize, opcodes: F) -> Option<Instruction>
+where
+    F: Fn(i64) -> Option<&'static OpcodeInfo>,
+{
+    let word = *program.get(address)?;
+    if word < 0 {
+        return None;
+    }
+
+    let opcode = opcodes(word % 100)?;
+    let mut modes = word / 100;
+    let mut params = Vec::new();
+    for i in 0..opcode.inputs + opcode.outputs {
+        let value = *program.get(address + 1 + i)?;
+        let is_output = i >= opcode.inputs;
+        params.push(match (modes % 10, is_output) {
+            (0, _) => Param::Position(value),
+            (1, false) => Param::Immediate(value),
+            (2, _) => Param::Relative(value),
+            _ => return None,
+        });
+        modes /= 10;
+    }
+    if modes != 0 {
+        return None;
+    }
+
+    Some(Instruction {
+        address,
+        opcode,
+        params,
+    })
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Line {
+    Code(Instruction),
+    Data { address: usize, values: Vec<i64> },
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Line::Code(instruction) => write!(f, "{:>5}: {}", instruction.address, instruction),
+            Line::Data { address, values } => {
+                let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                write!(f, "{:>5}: data {}", address, values.join(", "))
+            }
+        }
+    }
+}
+
+const DATA_PER_LINE: usize = 8;
+
+/// Linear sweep disassembly: every word that starts a valid instruction is decoded as one and
+/// anything else is shown as raw data.
+pub fn disassemble(program: &[i64]) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut address = 0;
+    while address < program.len() {
+        if let Some(instruction) = decode(program, address) {
+            address += instruction.size();
+            lines.push(Line::Code(instruction));
+            continue;
+        }
+
+        match lines.last_mut() {
+            Some(Line::Data { values, .. }) if values.len() < DATA_PER_LINE => {
+                values.push(program[address])
+            }
+            _ => lines.push(Line::Data {
+                address,
+                values: vec![program[address]],
+            }),
+        }
+        address += 1;
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listing(program: &[i64]) -> Vec<String> {
+        disassemble(program).iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn test_disassemble() {
+        assert_eq!(
+            listing(&[1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50]),
+            vec![
+                "    0: add [9], [10], [3]",
+                "    4: mul [3], [11], [0]",
+                "    8: hlt",
+                "    9: data 30, 40, 50",
+            ]
+        );
+        assert_eq!(
+            listing(&[109, -1, 21101, 2, 3, -4, 1206, 7, 0, 204, 1]),
+            vec![
+                "    0: arb #-1",
+                "    2: add #2, #3, [rb-4]",
+                "    6: jf [rb+7], #0",
+                "    9: out [rb+1]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_instructions_are_data() {
+        // Immediate mode output, a stray mode digit, and parameters past the end of the program
+        assert_eq!(
+            listing(&[11101, 0, 0, 0, 1104, 7, 3]),
+            vec!["    0: data 11101, 0, 0, 0, 1104, 7, 3"]
+        );
+        assert_eq!(
+            listing(&[0, 0, 0, 0, 0, 0, 0, 0, 42, 99]),
+            vec![
+                "    0: data 0, 0, 0, 0, 0, 0, 0, 0",
+                "    8: data 42",
+                "    9: hlt"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_opcode_table() {
+        for op in OPCODES {
+            assert_eq!(opcode_info(op.code), Some(op));
+        }
+        assert_eq!(opcode_info(3).unwrap().mnemonic, "in");
+        assert!(opcode_info(0).is_none());
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::VecDeque;
+
+use super::trace::{MemoryWrite, TraceEntry};
+use super::{IntcodeMemory, IntcodeVM, VMState, Word};
+
+/// What it takes to undo one step: the registers and state from before it, the input it took
+/// off the queue, and the old value of every cell it wrote.
+struct Checkpoint<W> {
+    ip: usize,
+    relative_base: i64,
+    state: VMState<W>,
+    consumed: Option<W>,
+    writes: Vec<MemoryWrite<W>>,
+}
+
+/// The most recent steps a VM has taken, kept so they can be undone.
+pub struct History<W = i64> {
+    checkpoints: VecDeque<Checkpoint<W>>,
+    capacity: usize,
+}
+
+impl<W: Word> History<W> {
+    /// Keeps at most `capacity` steps, dropping the oldest once it is full.
+    pub fn new(capacity: usize) -> History<W> {
+        History {
+            checkpoints: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.checkpoints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.checkpoints.is_empty()
+    }
+
+    fn push(&mut self, checkpoint: Checkpoint<W>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.checkpoints.len() == self.capacity {
+            self.checkpoints.pop_front();
+        }
+        self.checkpoints.push_back(checkpoint);
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.checkpoints.clear();
+    }
+
+    pub(super) fn record(&mut self, entry: &TraceEntry<W>, state: VMState<W>, consumed: Option<W>) {
+        self.push(Checkpoint {
+            ip: entry.ip,
+            relative_base: entry.relative_base,
+            state,
+            consumed,
+            writes: entry.writes.clone(),
+        });
+    }
+
+    /// Records a step that moves the VM on without touching memory or input.
+    pub(super) fn record_move(&mut self, ip: usize, relative_base: i64, state: VMState<W>) {
+        self.push(Checkpoint {
+            ip,
+            relative_base,
+            state,
+            consumed: None,
+            writes: Vec::new(),
+        });
+    }
+}
+
+impl<D: IntcodeMemory> IntcodeVM<D> {
+    /// Starts keeping `history` of every step so they can be undone with `step_back`, or stops
+    /// when given `None`. Returns the history that was previously kept.
+    pub fn set_history(&mut self, history: Option<History<D::Word>>) -> Option<History<D::Word>> {
+        std::mem::replace(&mut self.history, history)
+    }
+
+    pub fn history(&self) -> Option<&History<D::Word>> {
+        self.history.as_ref()
+    }
+
+    fn undo(&mut self, checkpoint: Checkpoint<D::Word>) {
+        for write in checkpoint.writes.into_iter().rev() {
+            if let Some(cache) = &mut self.cache {
+                cache.invalidate(write.address);
+            }
+            if let Some(cell) = self.data.load_mut(write.address) {
+                *cell = write.old;
+            }
+        }
+        if let Some(loops) = &mut self.loops {
+            loops.reset();
+        }
+        self.ip = checkpoint.ip;
+        self.relative_base = checkpoint.relative_base;
+        self.state = checkpoint.state;
+        if let Some(i) = checkpoint.consumed {
+            self.inputs.push_front(i);
+        }
+    }
+
+    /// Undoes the most recent step, returning false if there is no history left to undo.
+    pub fn step_back(&mut self) -> bool {
+        match self.history.as_mut().and_then(|h| h.checkpoints.pop_back()) {
+            Some(checkpoint) => {
+                self.undo(checkpoint);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Steps back until just before the instruction that last changed the value at `address`,
+    /// which is left at the ip. Returns false if the history runs out first, leaving the VM at
+    /// the oldest step it remembers.
+    pub fn run_back_to_change(&mut self, address: usize) -> bool {
+        while let Some(checkpoint) = self.history.as_mut().and_then(|h| h.checkpoints.pop_back()) {
+            let changed = checkpoint
+                .writes
+                .iter()
+                .any(|w| w.address == address && w.old != w.new);
+            self.undo(checkpoint);
+            if changed {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{assemble, InterruptReason, PagedMemory, VMStatus};
+
+    fn recording(source: &str) -> IntcodeVM<PagedMemory> {
+        let program = assemble("test", source).unwrap();
+        let mut vm = IntcodeVM::new(PagedMemory::from(program));
+        vm.set_history(Some(History::new(1000)));
+        vm
+    }
+
+    #[test]
+    fn test_step_back() {
+        let mut vm = recording(
+            "
+                in [total]
+                arb #100
+            loop:
+                add [total], [rb+0], [total]
+                out [total]
+                in [rb+0]
+                jt [rb+0], #loop
+                hlt
+            total: data 0
+            ",
+        );
+        let start = vm.snapshot();
+        assert_eq!(vm.run_with_inputs(vec![1, 2, 0]).unwrap(), vec![1, 3]);
+
+        // Walking all the way back lands on exactly the state the run started from
+        while vm.step_back() {}
+        assert_eq!(vm.ip(), 0);
+        assert_eq!(vm.relative_base(), 0);
+        assert_eq!(
+            vm.queued_inputs().copied().collect::<Vec<_>>(),
+            vec![1, 2, 0]
+        );
+        let mut replay = IntcodeVM::from(start);
+        assert_eq!(
+            vm.run_with_inputs(vec![]).unwrap(),
+            replay.run_with_inputs(vec![1, 2, 0]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_step_back_over_interrupts() {
+        let mut vm = recording("in [9]\nout [9]\nhlt\ndata 0, 0, 0, 0, 0");
+        assert_eq!(vm.run().unwrap(), InterruptReason::WaitingForInput);
+        vm.input(5).unwrap();
+        assert_eq!(vm.run().unwrap(), InterruptReason::WaitingForOutput);
+        assert_eq!(vm.output().unwrap(), 5);
+
+        assert!(vm.step_back());
+        assert_eq!(vm.state(), VMStatus::WaitingForOutput);
+        assert!(vm.step_back());
+        assert_eq!((vm.ip(), vm.state()), (2, VMStatus::Ready));
+        assert!(vm.step_back());
+        assert_eq!(vm.state(), VMStatus::WaitingForInput);
+        assert_eq!(vm.data().load(9), Some(0));
+    }
+
+    #[test]
+    fn test_run_back_to_change() {
+        let mut vm = recording(
+            "
+                add #3, #0, [x]
+                add #5, #0, [y]
+                add [x], #0, [x]    ; writes the same value back
+                add [y], #0, [y]
+                hlt
+            x:  data 0
+            y:  data 0
+            ",
+        );
+        vm.run().unwrap();
+        assert!(vm.run_back_to_change(17));
+        assert_eq!(vm.ip(), 0);
+        assert_eq!(vm.data().load(17), Some(0));
+        assert_eq!(vm.history().unwrap().len(), 0);
+        assert!(!vm.run_back_to_change(17));
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use super::trace::TraceEntry;
+use super::{IntcodeMemory, IntcodeVM, Word};
+
+fn hash<T: Hash>(value: T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Watches for the VM coming back to a state it has already been in without taking any input
+/// since. From there it can only go round the same way again, forever.
+///
+/// A state is the ip, the relative base and every cell of memory, but only cells the program
+/// has written can differ, so memory is hashed as the XOR of a hash of each write's address and
+/// value, undoing the one for the value it replaced. States are checked with Brent's algorithm:
+/// one earlier state is kept, moved forward each time the number of steps since it was taken
+/// reaches a doubling limit, and every state is compared against it. Matching hashes are
+/// confirmed against the cells written since, so a loop is only reported when there is one.
+pub(super) struct LoopDetector<W> {
+    memory: u64,
+    saved: Option<(usize, i64, u64)>,
+    // The value each cell written since the saved state had when it was saved
+    saved_cells: HashMap<usize, W>,
+    steps: u64,
+    limit: u64,
+}
+
+impl<W: Word> LoopDetector<W> {
+    fn new() -> LoopDetector<W> {
+        LoopDetector {
+            memory: 0,
+            saved: None,
+            saved_cells: HashMap::new(),
+            steps: 0,
+            limit: 1,
+        }
+    }
+
+    /// Forgets every state seen so far, for when the VM changed in a way that wasn't recorded or
+    /// that the program had no say in.
+    pub(super) fn reset(&mut self) {
+        self.saved = None;
+        self.saved_cells.clear();
+        self.steps = 0;
+        self.limit = 1;
+    }
+
+    /// Takes in a step that ran to completion, leaving the VM at `ip` with relative base
+    /// `relative_base` and memory `data`. Returns whether the VM has been here before.
+    pub(super) fn record<D: IntcodeMemory<Word = W>>(
+        &mut self,
+        entry: &TraceEntry<W>,
+        ip: usize,
+        relative_base: i64,
+        data: &D,
+    ) -> bool {
+        for write in &entry.writes {
+            if write.old != write.new {
+                self.memory ^=
+                    hash((write.address, &write.old)) ^ hash((write.address, &write.new));
+                self.saved_cells
+                    .entry(write.address)
+                    .or_insert_with(|| write.old.clone());
+            }
+        }
+
+        let state = self.memory ^ hash((ip, relative_base));
+        if self.saved == Some((ip, relative_base, state))
+            && self
+                .saved_cells
+                .iter()
+                .all(|(&address, old)| data.load(address).as_ref() == Some(old))
+        {
+            return true;
+        }
+
+        self.steps += 1;
+        if self.steps == self.limit {
+            self.saved = Some((ip, relative_base, state));
+            self.saved_cells.clear();
+            self.steps = 0;
+            self.limit *= 2;
+        }
+        false
+    }
+}
+
+impl<D: IntcodeMemory> IntcodeVM<D> {
+    /// Turns loop detection on or off. With it on, a run that comes back to the same state
+    /// without taking input stops with `InterruptReason::InfiniteLoop`. It has to see every
+    /// step, so the VM runs as slowly as it does while tracing.
+    pub fn set_loop_detection(&mut self, enabled: bool) {
+        self.loops = match enabled {
+            true => Some(LoopDetector::new()),
+            false => None,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::{assemble, IntcodeErrorKind, IntcodeVM, InterruptReason, PagedMemory};
+
+    fn detect(source: &str, inputs: Vec<i64>) -> (InterruptReason, Vec<i64>) {
+        let mut vm = IntcodeVM::new(PagedMemory::from(assemble("test", source).unwrap()));
+        vm.set_loop_detection(true);
+        for i in inputs {
+            vm.push_input(i);
+        }
+        let mut outputs = Vec::new();
+        let reason = vm.run_collecting(&mut outputs).unwrap();
+        (reason, outputs)
+    }
+
+    #[test]
+    fn test_loop_detection() {
+        // Flips a cell back and forth forever, printing it each time round
+        let (reason, outputs) = detect(
+            "
+            loop:
+                eq [x], #0, [x]
+                out [x]
+                jt #1, #loop
+            x:  data 0
+            ",
+            vec![],
+        );
+        assert_eq!(reason, InterruptReason::InfiniteLoop);
+        assert_eq!(outputs[..2], [1, 0]);
+
+        // Counting down always reaches a new state, but waiting on a zero never does
+        let counter = "
+                in [n]
+            wait:
+                jf [n], #wait
+            count:
+                add [n], #-1, [n]
+                jt [n], #count
+                in [n]
+                jt #1, #wait
+            n:  data 0
+        ";
+        let (reason, _) = detect(counter, vec![1000, 3]);
+        assert_eq!(reason, InterruptReason::WaitingForInput);
+        let (reason, _) = detect(counter, vec![1000, 0]);
+        assert_eq!(reason, InterruptReason::InfiniteLoop);
+
+        // Which isn't the program running to completion
+        let mut vm = IntcodeVM::new(PagedMemory::from(assemble("test", counter).unwrap()));
+        vm.set_loop_detection(true);
+        assert_eq!(
+            vm.run_with_inputs(vec![0]).unwrap_err().kind,
+            IntcodeErrorKind::Interrupted(InterruptReason::InfiniteLoop)
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use std::convert::TryFrom;
+use std::ops::DerefMut;
+use std::sync::Arc;
+
+use super::Word;
+
+/// Backing store for an `IntcodeVM`. `load` and `load_mut` return `None` for addresses the
+/// backend can't serve, which the VM reports as an out of bounds dereference. `Word` is what
+/// each cell holds, and so what the VM computes with.
+pub trait IntcodeMemory {
+    type Word: Word;
+
+    fn load(&self, index: usize) -> Option<Self::Word>;
+    fn load_mut(&mut self, index: usize) -> Option<&mut Self::Word>;
+    /// The `N` cells from `start` on, or `None` if any of them can't be loaded. This is how
+    /// instructions fetch their operands, so backends that can check all `N` at once should.
+    #[inline]
+    fn load_run<const N: usize>(&self, start: usize) -> Option<[Self::Word; N]> {
+        let mut cells: [Self::Word; N] = std::array::from_fn(|_| Self::Word::default());
+        for (i, cell) in cells.iter_mut().enumerate() {
+            *cell = self.load(start.checked_add(i)?)?;
+        }
+        Some(cells)
+    }
+    /// The number of cells that may hold something. Everything from here on is either zero or
+    /// out of bounds.
+    fn extent(&self) -> usize;
+}
+
+// Anything that derefs to a slice is a fixed-size memory: the program image and nothing past it.
+impl<W: Word, D: DerefMut<Target = [W]>> IntcodeMemory for D {
+    type Word = W;
+
+    #[inline]
+    fn load(&self, index: usize) -> Option<W> {
+        self.deref().get(index).cloned()
+    }
+
+    #[inline]
+    fn load_mut(&mut self, index: usize) -> Option<&mut W> {
+        self.deref_mut().get_mut(index)
+    }
+
+    #[inline]
+    fn load_run<const N: usize>(&self, start: usize) -> Option<[W; N]> {
+        let cells = self.deref().get(start..start.checked_add(N)?)?;
+        <&[W; N]>::try_from(cells).ok().cloned()
+    }
+
+    fn extent(&self) -> usize {
+        self.deref().len()
+    }
+}
+
+/// Memory that can be made from nothing but a size, every cell zero, which is how a snapshot
+/// builds it before writing back the cells that weren't.
+pub trait ZeroedMemory: IntcodeMemory + Sized {
+    /// The largest size it makes sense to ask for. Snapshots that claim more are refused rather
+    /// than trusted with an allocation.
+    const MAX_SIZE: usize;
+
+    fn zeroed(size: usize) -> Self;
+}
+
+// A program image as long as the largest real program several thousand times over
+impl<W: Word> ZeroedMemory for Vec<W> {
+    const MAX_SIZE: usize = 1 << 24;
+
+    fn zeroed(size: usize) -> Vec<W> {
+        vec![W::default(); size]
+    }
+}
+
+// Small pages, so a fork that writes to a program a few hundred words long copies only the
+// parts it writes to rather than the whole image
+const PAGE_BITS: usize = 8;
+const PAGE_SIZE: usize = 1 << PAGE_BITS;
+// Far enough out for any real program, close enough that a garbage pointer is an error rather
+// than a multi-gigabyte page table.
+const MAX_ADDRESS: usize = 1 << 32;
+
+type Page<W> = [W; PAGE_SIZE];
+
+/// Unbounded, zero-initialised memory. Reads anywhere succeed and reads of untouched cells give
+/// zero; pages are only allocated once something is written to them.
+///
+/// Pages are shared copy-on-write, so cloning only copies the page table. A clone (and so a
+/// forked VM) pays for a page the first time either side writes to it.
+#[derive(Clone, Default)]
+pub struct PagedMemory<W: Word = i64> {
+    pages: Vec<Option<Arc<Page<W>>>>,
+}
+
+impl<W: Word> PagedMemory<W> {
+    pub fn new() -> PagedMemory<W> {
+        PagedMemory { pages: Vec::new() }
+    }
+
+    fn page_mut(&mut self, page: usize) -> &mut Page<W> {
+        if self.pages.len() <= page {
+            self.pages.resize_with(page + 1, || None);
+        }
+        let page =
+            self.pages[page].get_or_insert_with(|| Arc::new(std::array::from_fn(|_| W::default())));
+        Arc::make_mut(page)
+    }
+}
+
+impl<W: Word> From<&[W]> for PagedMemory<W> {
+    fn from(program: &[W]) -> PagedMemory<W> {
+        let mut memory = PagedMemory::new();
+        for (page, chunk) in program.chunks(PAGE_SIZE).enumerate() {
+            memory.page_mut(page)[..chunk.len()].clone_from_slice(chunk);
+        }
+        memory
+    }
+}
+
+impl<W: Word> From<Vec<W>> for PagedMemory<W> {
+    fn from(program: Vec<W>) -> PagedMemory<W> {
+        PagedMemory::from(program.as_slice())
+    }
+}
+
+impl<W: Word> IntcodeMemory for PagedMemory<W> {
+    type Word = W;
+
+    fn load(&self, index: usize) -> Option<W> {
+        if index >= MAX_ADDRESS {
+            return None;
+        }
+        match self.pages.get(index >> PAGE_BITS) {
+            Some(Some(page)) => Some(page[index & (PAGE_SIZE - 1)].clone()),
+            _ => Some(W::default()),
+        }
+    }
+
+    fn load_mut(&mut self, index: usize) -> Option<&mut W> {
+        if index >= MAX_ADDRESS {
+            return None;
+        }
+        Some(&mut self.page_mut(index >> PAGE_BITS)[index & (PAGE_SIZE - 1)])
+    }
+
+    fn extent(&self) -> usize {
+        self.pages.len() << PAGE_BITS
+    }
+}
+
+// Untouched pages read as zero already, so nothing is allocated until a cell is written
+impl<W: Word> ZeroedMemory for PagedMemory<W> {
+    const MAX_SIZE: usize = MAX_ADDRESS;
+
+    fn zeroed(_size: usize) -> PagedMemory<W> {
+        PagedMemory::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paged_memory() {
+        let end = 2 * PAGE_SIZE - 50;
+        let program: Vec<i64> = (0..end as i64).collect();
+        let mut memory = PagedMemory::from(program);
+        assert_eq!(memory.load(end - 1), Some(end as i64 - 1));
+        assert_eq!(memory.load(end), Some(0));
+        assert_eq!(memory.load(1 << 20), Some(0));
+        assert_eq!(memory.pages.len(), 2);
+        assert_eq!(memory.extent(), 2 * PAGE_SIZE);
+
+        *memory.load_mut(1 << 20).unwrap() = 7;
+        assert_eq!(memory.load(1 << 20), Some(7));
+        assert_eq!(memory.load((1 << 20) + 1), Some(0));
+        assert_eq!(memory.pages.iter().filter(|p| p.is_some()).count(), 3);
+
+        assert_eq!(memory.load(MAX_ADDRESS), None);
+        assert!(memory.load_mut(MAX_ADDRESS).is_none());
+    }
+
+    fn shared_pages(a: &PagedMemory, b: &PagedMemory) -> usize {
+        a.pages
+            .iter()
+            .zip(b.pages.iter())
+            .filter(|(a, b)| match (a, b) {
+                (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+                _ => false,
+            })
+            .count()
+    }
+
+    #[test]
+    fn test_copy_on_write() {
+        let program: Vec<i64> = (0..3 * PAGE_SIZE as i64 - 50).collect();
+        let original = PagedMemory::from(program);
+        let mut fork = original.clone();
+        assert_eq!(shared_pages(&original, &fork), 3);
+
+        let a = PAGE_SIZE + 10;
+        *fork.load_mut(a).unwrap() = -1;
+        assert_eq!(shared_pages(&original, &fork), 2);
+        assert_eq!(original.load(a), Some(a as i64));
+        assert_eq!(fork.load(a), Some(-1));
+        assert_eq!(fork.load(a + 1), Some(a as i64 + 1));
+
+        // Once a page has been copied, later writes to it don't copy it again
+        let copied = fork.pages[1].as_ref().map(Arc::as_ptr);
+        *fork.load_mut(a + 1).unwrap() = -2;
+        assert_eq!(fork.pages[1].as_ref().map(Arc::as_ptr), copied);
+    }
+}
@@ -0,0 +1,225 @@
+use std::error::Error;
+use std::fmt;
+
+use super::{IntcodeError, IntcodeMemory, IntcodeVM, InterruptReason};
+
+/// Several VMs wired output-to-input. Each VM's output goes to at most one other VM's input
+/// queue, or to the network's own output if it isn't connected anywhere. Links may form cycles.
+pub struct IntcodeNetwork<D: IntcodeMemory> {
+    nodes: Vec<Node<D>>,
+    outputs: Vec<i64>,
+}
+
+struct Node<D: IntcodeMemory> {
+    vm: IntcodeVM<D>,
+    target: Option<usize>,
+    status: Option<InterruptReason>,
+    last_output: Option<i64>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum NetworkOutcome {
+    Terminated,
+    /// Every VM still running, as listed, is waiting for input that nothing is left to send.
+    Deadlock(Vec<usize>),
+    /// The loop detector stopped the VMs listed. The rest have terminated or are waiting for
+    /// input.
+    InfiniteLoop(Vec<usize>),
+}
+
+#[derive(Debug)]
+pub struct NetworkError {
+    pub vm: usize,
+    pub error: IntcodeError,
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "VM {} faulted: {}", self.vm, self.error)
+    }
+}
+
+impl Error for NetworkError {}
+
+impl<D: IntcodeMemory<Word = i64>> Default for IntcodeNetwork<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D: IntcodeMemory<Word = i64>> IntcodeNetwork<D> {
+    pub fn new() -> IntcodeNetwork<D> {
+        IntcodeNetwork {
+            nodes: Vec::new(),
+            outputs: Vec::new(),
+        }
+    }
+
+    /// Adds a VM to the network, returning the index it is known by.
+    pub fn add(&mut self, vm: IntcodeVM<D>) -> usize {
+        self.nodes.push(Node {
+            vm,
+            target: None,
+            status: None,
+            last_output: None,
+        });
+        self.nodes.len() - 1
+    }
+
+    /// Sends everything `from` outputs to `to`'s input queue, replacing any earlier link.
+    pub fn connect(&mut self, from: usize, to: usize) {
+        self.nodes[from].target = Some(to);
+    }
+
+    pub fn push_input(&mut self, vm: usize, i: i64) {
+        self.nodes[vm].vm.push_input(i);
+    }
+
+    /// Output from VMs that aren't connected to anything, in the order it was produced.
+    pub fn outputs(&self) -> &[i64] {
+        &self.outputs
+    }
+
+    pub fn last_output(&self, vm: usize) -> Option<i64> {
+        self.nodes[vm].last_output
+    }
+
+    fn is_blocked(node: &Node<D>) -> bool {
+        match node.status {
+            Some(InterruptReason::Terminate) | Some(InterruptReason::InfiniteLoop) => true,
+            Some(InterruptReason::WaitingForInput) => node.vm.inputs.is_empty(),
+            _ => false,
+        }
+    }
+
+    /// Runs the VMs round robin, each until it terminates or runs out of input, until either
+    /// all of them have terminated or none of them can make progress.
+    pub fn run(&mut self) -> Result<NetworkOutcome, NetworkError> {
+        let mut sent = Vec::new();
+        loop {
+            let mut progressed = false;
+            for i in 0..self.nodes.len() {
+                if Self::is_blocked(&self.nodes[i]) {
+                    continue;
+                }
+                progressed = true;
+
+                let node = &mut self.nodes[i];
+                let reason = node
+                    .vm
+                    .run_collecting(&mut sent)
+                    .map_err(|error| NetworkError { vm: i, error })?;
+                node.status = Some(reason);
+                if let Some(&last) = sent.last() {
+                    node.last_output = Some(last);
+                }
+
+                match node.target {
+                    Some(target) => {
+                        for v in sent.drain(..) {
+                            self.nodes[target].vm.push_input(v);
+                        }
+                    }
+                    None => self.outputs.append(&mut sent),
+                }
+            }
+
+            if !progressed {
+                break;
+            }
+        }
+
+        let stuck_on = |reason| -> Vec<usize> {
+            (0..self.nodes.len())
+                .filter(|&i| self.nodes[i].status == Some(reason))
+                .collect()
+        };
+        let looping = stuck_on(InterruptReason::InfiniteLoop);
+        let waiting = stuck_on(InterruptReason::WaitingForInput);
+        if !looping.is_empty() {
+            Ok(NetworkOutcome::InfiniteLoop(looping))
+        } else if !waiting.is_empty() {
+            Ok(NetworkOutcome::Deadlock(waiting))
+        } else {
+            Ok(NetworkOutcome::Terminated)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::IntcodeErrorKind;
+
+    fn amplifiers(program: &[i64], phases: &[i64], feedback: bool) -> IntcodeNetwork<Vec<i64>> {
+        let mut net = IntcodeNetwork::new();
+        for &phase in phases {
+            let vm = net.add(IntcodeVM::new(program.to_vec()));
+            net.push_input(vm, phase);
+        }
+        for i in 1..phases.len() {
+            net.connect(i - 1, i);
+        }
+        if feedback {
+            net.connect(phases.len() - 1, 0);
+        }
+        net.push_input(0, 0);
+        net
+    }
+
+    #[test]
+    fn test_chain() {
+        let program = vec![
+            3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0,
+        ];
+        let mut net = amplifiers(&program, &[4, 3, 2, 1, 0], false);
+        assert_eq!(net.run().unwrap(), NetworkOutcome::Terminated);
+        assert_eq!(net.outputs(), &[43210]);
+    }
+
+    #[test]
+    fn test_feedback_loop() {
+        let program = vec![
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1,
+            28, 1005, 28, 6, 99, 0, 0, 5,
+        ];
+        let mut net = amplifiers(&program, &[9, 8, 7, 6, 5], true);
+        assert_eq!(net.run().unwrap(), NetworkOutcome::Terminated);
+        assert_eq!(net.last_output(4), Some(139629729));
+        assert!(net.outputs().is_empty());
+    }
+
+    #[test]
+    fn test_deadlock() {
+        // Two VMs that each wait to hear from the other before saying anything
+        let program = vec![3, 7, 4, 7, 1105, 1, 0, 0];
+        let mut net = IntcodeNetwork::new();
+        let a = net.add(IntcodeVM::new(program.clone()));
+        let b = net.add(IntcodeVM::new(program));
+        net.connect(a, b);
+        net.connect(b, a);
+        assert_eq!(net.run().unwrap(), NetworkOutcome::Deadlock(vec![a, b]));
+    }
+
+    #[test]
+    fn test_infinite_loop() {
+        // One VM spins forever while the other waits on it for input
+        let mut spinner = IntcodeVM::new(vec![1105, 1, 0]);
+        spinner.set_loop_detection(true);
+        let mut net = IntcodeNetwork::new();
+        let a = net.add(spinner);
+        let b = net.add(IntcodeVM::new(vec![3, 0, 99]));
+        net.connect(a, b);
+        assert_eq!(net.run().unwrap(), NetworkOutcome::InfiniteLoop(vec![a]));
+    }
+
+    #[test]
+    fn test_fault() {
+        let mut net = IntcodeNetwork::new();
+        net.add(IntcodeVM::new(vec![99]));
+        net.add(IntcodeVM::new(vec![42]));
+        let err = net.run().unwrap_err();
+        assert_eq!(err.vm, 1);
+        assert_eq!(err.error.kind, IntcodeErrorKind::IllegalOpcode(42));
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use super::disasm::{decode_with, Param};
+use super::trace::TraceEntry;
+use super::{IntcodeMemory, IntcodeVM, Word};
+
+// Deeper than any real program nests; stops a runaway stack from eating memory.
+const MAX_DEPTH: usize = 64;
+
+/// A region of code entered by a jump, standing in for a function or loop body in the folded
+/// stacks. `resume` is the address just after the jump the region was last left by, so a jump
+/// back to it can be recognised as a return.
+struct Frame {
+    start: usize,
+    resume: usize,
+}
+
+/// Execution counts gathered while a VM runs: per instruction address, per opcode, per memory
+/// cell read and written, and per stack of jump targets for flame graphs.
+pub struct Profile {
+    total: u64,
+    executions: HashMap<usize, u64>,
+    opcodes: HashMap<i64, u64>,
+    reads: HashMap<usize, u64>,
+    writes: HashMap<usize, u64>,
+    back_edges: HashMap<(usize, usize), u64>,
+    frames: Vec<Frame>,
+    stacks: HashMap<Vec<usize>, u64>,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile {
+            total: 0,
+            executions: HashMap::new(),
+            opcodes: HashMap::new(),
+            reads: HashMap::new(),
+            writes: HashMap::new(),
+            back_edges: HashMap::new(),
+            // Everything up to the first jump runs in a frame for the program's entry point
+            frames: vec![Frame {
+                start: 0,
+                resume: 0,
+            }],
+            stacks: HashMap::new(),
+        }
+    }
+
+    pub fn executions(&self, address: usize) -> u64 {
+        self.executions.get(&address).copied().unwrap_or(0)
+    }
+
+    pub(super) fn record<W: Word>(&mut self, entry: &TraceEntry<W>, jumped_to: Option<usize>) {
+        self.total += 1;
+        *self.executions.entry(entry.ip).or_default() += 1;
+        *self
+            .opcodes
+            .entry(entry.instruction.clamp_to_i64() % 100)
+            .or_default() += 1;
+        if let Some(decoded) = &entry.decoded {
+            for param in &decoded.params[..decoded.opcode.inputs] {
+                let address = match *param {
+                    Param::Position(a) => a,
+                    Param::Relative(a) => entry.relative_base.saturating_add(a),
+                    Param::Immediate(_) => continue,
+                };
+                if address >= 0 {
+                    *self.reads.entry(address as usize).or_default() += 1;
+                }
+            }
+        }
+        self.record_writes(entry);
+
+        let stack: Vec<usize> = self.frames.iter().map(|f| f.start).collect();
+        *self.stacks.entry(stack).or_default() += 1;
+        if let Some(target) = jumped_to {
+            if target <= entry.ip {
+                *self.back_edges.entry((target, entry.ip)).or_default() += 1;
+            }
+            let next = entry
+                .decoded
+                .as_ref()
+                .map_or(entry.ip, |decoded| entry.ip + decoded.size());
+            self.enter(next, target);
+        }
+    }
+
+    pub(super) fn record_writes<W>(&mut self, entry: &TraceEntry<W>) {
+        for write in &entry.writes {
+            *self.writes.entry(write.address).or_default() += 1;
+        }
+    }
+
+    /// Moves the frame stack for a jump to `target` by the instruction that ends just before
+    /// `next`. Jumping back into a frame that's already on the stack, either to its start or to
+    /// the instruction after the jump that left it, unwinds to it; anything else enters a new
+    /// frame.
+    fn enter(&mut self, next: usize, target: usize) {
+        if let Some(top) = self.frames.last_mut() {
+            top.resume = next;
+        }
+        let returning_to = self
+            .frames
+            .iter()
+            .rposition(|f| f.start == target || f.resume == target);
+        match returning_to {
+            Some(i) => self.frames.truncate(i + 1),
+            None if self.frames.len() < MAX_DEPTH => self.frames.push(Frame {
+                start: target,
+                resume: target,
+            }),
+            None => {}
+        }
+    }
+
+    /// Writes one line per distinct stack of frames, in the folded format flame graph tools
+    /// read. Each frame is named after the address it was entered at.
+    pub fn write_folded<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut stacks: Vec<(&Vec<usize>, &u64)> = self.stacks.iter().collect();
+        stacks.sort();
+        for (stack, count) in stacks {
+            let frames: Vec<String> = stack.iter().map(|a| format!("@{}", a)).collect();
+            writeln!(out, "{} {}", frames.join(";"), count)?;
+        }
+        Ok(())
+    }
+
+    /// Prints the `top` hottest instructions, loops and memory cells, plus counts per opcode.
+    /// `vm`'s memory and opcodes, including any it has had added, are used to show what each hot
+    /// instruction is.
+    pub fn report<W: Write, D: IntcodeMemory>(
+        &self,
+        out: &mut W,
+        vm: &IntcodeVM<D>,
+        top: usize,
+    ) -> io::Result<()> {
+        let percent = |n: u64| 100.0 * n as f64 / self.total.max(1) as f64;
+        writeln!(out, "{} instructions executed", self.total)?;
+
+        writeln!(out, "\nHottest instructions:")?;
+        for (address, count) in hottest(&self.executions, top) {
+            let words: Vec<i64> = (address..address + 4)
+                .map_while(|a| vm.data().load(a).map(|w| w.clamp_to_i64()))
+                .collect();
+            let text = match decode_with(&words, 0, |code| vm.opcode_info(code)) {
+                Some(instruction) => instruction.to_string(),
+                None => format!("data {}", words.first().copied().unwrap_or(0)),
+            };
+            writeln!(
+                out,
+                "{:>12} {:>5.1}% {:>6}: {}",
+                count,
+                percent(count),
+                address,
+                text
+            )?;
+        }
+
+        writeln!(out, "\nBy opcode:")?;
+        let mut opcodes: Vec<(&i64, &u64)> = self.opcodes.iter().collect();
+        opcodes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (&code, &count) in opcodes {
+            let name = vm.opcode_info(code).map_or("?", |op| op.mnemonic);
+            writeln!(out, "{:>12} {:>5.1}% {}", count, percent(count), name)?;
+        }
+
+        // A backwards jump only taken once is more likely a return than a loop
+        writeln!(out, "\nHot loops:")?;
+        let mut loops: Vec<((usize, usize), u64)> = self
+            .back_edges
+            .iter()
+            .filter(|(_, &iterations)| iterations > 1)
+            .map(|(&(start, end), _)| {
+                let inside = (start..=end).map(|a| self.executions(a)).sum();
+                ((start, end), inside)
+            })
+            .collect();
+        loops.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for ((start, end), inside) in loops.into_iter().take(top) {
+            let iterations = self.back_edges[&(start, end)];
+            writeln!(
+                out,
+                "{:>12} {:>5.1}% {:>6}..{}: {} iterations",
+                inside,
+                percent(inside),
+                start,
+                end,
+                iterations
+            )?;
+        }
+
+        writeln!(out, "\nMost accessed cells:")?;
+        let mut accesses: HashMap<usize, u64> = self.reads.clone();
+        for (&address, &count) in &self.writes {
+            *accesses.entry(address).or_default() += count;
+        }
+        for (address, _) in hottest(&accesses, top) {
+            writeln!(
+                out,
+                "{:>6}: {} reads, {} writes",
+                address,
+                self.reads.get(&address).copied().unwrap_or(0),
+                self.writes.get(&address).copied().unwrap_or(0)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn hottest(counts: &HashMap<usize, u64>, top: usize) -> Vec<(usize, u64)> {
+    let mut counts: Vec<(usize, u64)> = counts.iter().map(|(&a, &c)| (a, c)).collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    counts.truncate(top);
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{
+        assemble, IntcodeErrorKind, IntcodeVM, OpcodeInfo, PagedMemory, StepResult,
+    };
+
+    fn profiled(source: &str, inputs: Vec<i64>) -> IntcodeVM<PagedMemory> {
+        let program = assemble("test", source).unwrap();
+        let mut vm = IntcodeVM::new(PagedMemory::from(program));
+        vm.set_profile(Some(Profile::new()));
+        vm.run_with_inputs(inputs).unwrap();
+        vm
+    }
+
+    // Calls a subroutine that counts down from its argument, passing the return address on the
+    // relative base stack the way compiled Intcode does
+    const CALLS: &str = "
+            arb #100
+            in [rb+1]
+            add #return, #0, [rb+0]
+            jt #1, #countdown
+        return:
+            hlt
+
+        countdown:
+            add [rb+1], #-1, [rb+1]
+            jt [rb+1], #countdown
+            jt #1, [rb+0]
+    ";
+
+    #[test]
+    fn test_counts() {
+        let vm = profiled(CALLS, vec![3]);
+        let profile = vm.profile().unwrap();
+        // Four instructions to make the call, three times round the loop, the return and the halt
+        assert_eq!(profile.total, 4 + 3 * 2 + 1 + 1);
+        assert_eq!(profile.executions(12), 3);
+        assert_eq!(profile.opcodes[&1], 4);
+        assert_eq!(profile.reads[&101], 6);
+        assert_eq!(profile.writes[&101], 4);
+        assert_eq!(profile.back_edges[&(12, 16)], 2);
+    }
+
+    #[test]
+    fn test_folded_stacks() {
+        let vm = profiled(CALLS, vec![3]);
+        let mut folded = Vec::new();
+        vm.profile().unwrap().write_folded(&mut folded).unwrap();
+        assert_eq!(String::from_utf8(folded).unwrap(), "@0 5\n@0;@12 7\n");
+
+        let mut report = Vec::new();
+        let profile = vm.profile().unwrap();
+        profile.report(&mut report, &vm, 1).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.contains("     12..16: 2 iterations"), "{}", report);
+        assert!(report.contains("   101: 6 reads, 4 writes"), "{}", report);
+    }
+
+    static CALL: OpcodeInfo = OpcodeInfo {
+        code: 97,
+        mnemonic: "call",
+        inputs: 1,
+        outputs: 0,
+    };
+
+    fn call(
+        vm: &mut IntcodeVM<PagedMemory>,
+        inputs: &[i64],
+        _: &mut [i64],
+    ) -> Result<StepResult, IntcodeErrorKind> {
+        vm.ip = inputs[0] as usize;
+        Ok(StepResult::Jump)
+    }
+
+    #[test]
+    fn test_added_opcodes() {
+        // arb #100; add #8, #0, [rb+0]; call #10; hlt; data 0; jt #1, [rb+0]
+        let program = vec![109, 100, 21101, 8, 0, 0, 197, 10, 99, 0, 2105, 1, 0];
+        let mut vm = IntcodeVM::new(PagedMemory::from(program));
+        vm.add_opcode(&CALL, call);
+        vm.set_profile(Some(Profile::new()));
+        vm.run().unwrap();
+
+        // The two-word call returns to 8, so the halt is back in the entry frame
+        let profile = vm.profile().unwrap();
+        let mut folded = Vec::new();
+        profile.write_folded(&mut folded).unwrap();
+        assert_eq!(String::from_utf8(folded).unwrap(), "@0 4\n@0;@10 1\n");
+
+        let mut report = Vec::new();
+        profile.report(&mut report, &vm, 5).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.contains("     6: call #10"), "{}", report);
+        assert!(report.contains("% call\n"), "{}", report);
+    }
+}
@@ -0,0 +1,384 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::path::Path;
+
+use super::{
+    ImmediateInput, IntcodeMemory, IntcodeVM, PositionInput, PositionOutput, RelativeInput,
+    RelativeOutput, SomeInOpArg, SomeOutOpArg, VMState, Word, ZeroedMemory,
+};
+use crate::consume::{parse_all, ParseError, ParseResult, Scanner};
+use crate::futil::read_input;
+
+/// Everything needed to carry on running a VM from where it was: memory, ip, relative base, any
+/// half-finished input or output instruction and queued input. Tracing isn't part of a snapshot.
+#[derive(Clone)]
+pub struct Snapshot<D: IntcodeMemory> {
+    data: D,
+    ip: usize,
+    relative_base: i64,
+    state: VMState<D::Word>,
+    inputs: VecDeque<D::Word>,
+}
+
+// Memory is written as runs of non-zero cells; this many zeros in a row end a run.
+const ZERO_RUN: usize = 8;
+
+impl<D: IntcodeMemory + Clone> IntcodeVM<D> {
+    pub fn snapshot(&self) -> Snapshot<D> {
+        Snapshot {
+            data: self.data.clone(),
+            ip: self.ip,
+            relative_base: self.relative_base,
+            state: self.state.clone(),
+            inputs: self.inputs.clone(),
+        }
+    }
+
+    /// Puts the VM back the way it was when `snapshot` was taken. Tracing carries on as it is,
+    /// but any history and states the loop detector has seen are forgotten, since they no longer
+    /// lead up to the VM's state.
+    pub fn restore(&mut self, snapshot: &Snapshot<D>) {
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+        if let Some(loops) = &mut self.loops {
+            loops.reset();
+        }
+        if let Some(cache) = &mut self.cache {
+            cache.clear();
+        }
+        self.data = snapshot.data.clone();
+        self.ip = snapshot.ip;
+        self.relative_base = snapshot.relative_base;
+        self.state = snapshot.state.clone();
+        self.inputs = snapshot.inputs.clone();
+    }
+
+    /// A new VM, without any trace, history or decode cache, in the same state as this one and
+    /// with the same opcodes, which can then run independently.
+    pub fn fork(&self) -> IntcodeVM<D> {
+        let mut vm = IntcodeVM::from(self.snapshot());
+        vm.opcodes = self.opcodes.clone();
+        vm.arithmetic = self.arithmetic;
+        vm
+    }
+}
+
+impl<D: IntcodeMemory> From<Snapshot<D>> for IntcodeVM<D> {
+    fn from(snapshot: Snapshot<D>) -> IntcodeVM<D> {
+        let mut vm = IntcodeVM::new(snapshot.data);
+        vm.ip = snapshot.ip;
+        vm.relative_base = snapshot.relative_base;
+        vm.state = snapshot.state;
+        vm.inputs = snapshot.inputs;
+        vm
+    }
+}
+
+impl<D: IntcodeMemory> Snapshot<D> {
+    /// Writes the snapshot out as text that `parse` reads back.
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "intcode snapshot")?;
+        writeln!(w, "ip {}", self.ip)?;
+        writeln!(w, "relative_base {}", self.relative_base)?;
+        match &self.state {
+            VMState::Ready => writeln!(w, "state ready")?,
+            VMState::Fault => writeln!(w, "state fault")?,
+            VMState::WaitingForInput(SomeOutOpArg::Position(p)) => {
+                writeln!(w, "state waiting_for_input position {}", p.i)?
+            }
+            VMState::WaitingForInput(SomeOutOpArg::Relative(p)) => {
+                writeln!(w, "state waiting_for_input relative {}", p.i)?
+            }
+            VMState::WaitingForOutput(SomeInOpArg::Position(p)) => {
+                writeln!(w, "state waiting_for_output position {}", p.i)?
+            }
+            VMState::WaitingForOutput(SomeInOpArg::Immediate(p)) => {
+                writeln!(w, "state waiting_for_output immediate {}", p.i)?
+            }
+            VMState::WaitingForOutput(SomeInOpArg::Relative(p)) => {
+                writeln!(w, "state waiting_for_output relative {}", p.i)?
+            }
+        }
+        let inputs: Vec<String> = self.inputs.iter().map(|i| i.to_string()).collect();
+        writeln!(w, "inputs {}", inputs.join(", "))?;
+
+        let extent = self.data.extent();
+        let load = |address| self.data.load(address).unwrap_or_default();
+        writeln!(w, "size {}", extent)?;
+        let mut address = 0;
+        while address < extent {
+            if load(address).is_zero() {
+                address += 1;
+                continue;
+            }
+
+            let start = address;
+            let mut end = address;
+            let mut zeros = 0;
+            while address < extent && zeros < ZERO_RUN {
+                if load(address).is_zero() {
+                    zeros += 1;
+                } else {
+                    end = address + 1;
+                    zeros = 0;
+                }
+                address += 1;
+            }
+            let values: Vec<String> = (start..end).map(|a| load(a).to_string()).collect();
+            writeln!(w, "memory {}: {}", start, values.join(", "))?;
+        }
+        Ok(())
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = io::BufWriter::new(std::fs::File::create(path)?);
+        self.write(&mut file)?;
+        file.flush()
+    }
+}
+
+fn line<'a, T, F>(s: &mut Scanner<'a>, key: &str, f: F) -> ParseResult<T>
+where
+    F: FnOnce(&mut Scanner<'a>) -> ParseResult<T>,
+{
+    s.literal(key)?;
+    s.literal(" ")?;
+    let v = f(s)?;
+    s.literal("\n")?;
+    Ok(v)
+}
+
+impl<D: ZeroedMemory> Snapshot<D> {
+    pub fn parse(text: &str) -> ParseResult<Snapshot<D>> {
+        parse_all(text, |s| {
+            s.literal("intcode snapshot\n")?;
+            let ip = line(s, "ip", |s| s.int())?;
+            let relative_base = line(s, "relative_base", |s| s.int())?;
+            let state = line(s, "state", |s| {
+                let state = s.keyword(&[
+                    ("ready", 0),
+                    ("fault", 1),
+                    ("waiting_for_input", 2),
+                    ("waiting_for_output", 3),
+                ])?;
+                if state < 2 {
+                    return Ok(if state == 0 {
+                        VMState::Ready
+                    } else {
+                        VMState::Fault
+                    });
+                }
+
+                s.literal(" ")?;
+                let mode = s.keyword(&[("position", 0), ("immediate", 1), ("relative", 2)])?;
+                s.literal(" ")?;
+                let start = *s;
+                if (state, mode) == (2, 1) {
+                    return start.error("an operand that can be written to");
+                }
+                // Immediates are whole words; everything else is an address
+                if mode == 1 {
+                    let i = s.int()?;
+                    return Ok(VMState::WaitingForOutput(SomeInOpArg::Immediate(
+                        ImmediateInput { i },
+                    )));
+                }
+                let i = s.int()?;
+                Ok(match (state, mode) {
+                    (2, 0) => {
+                        VMState::WaitingForInput(SomeOutOpArg::Position(PositionOutput { i }))
+                    }
+                    (2, _) => {
+                        VMState::WaitingForInput(SomeOutOpArg::Relative(RelativeOutput { i }))
+                    }
+                    (_, 0) => VMState::WaitingForOutput(SomeInOpArg::Position(PositionInput { i })),
+                    _ => VMState::WaitingForOutput(SomeInOpArg::Relative(RelativeInput { i })),
+                })
+            })?;
+            let inputs = line(s, "inputs", |s| {
+                Ok(s.optional(|s| s.separated(", ", |s| s.int()))
+                    .unwrap_or_default())
+            })?;
+
+            let start = *s;
+            let size = line(s, "size", |s| s.int::<usize>())?;
+            if size > D::MAX_SIZE {
+                return start.error(format!("a size of at most {}", D::MAX_SIZE));
+            }
+            let mut memory = D::zeroed(size);
+            while !s.is_empty() {
+                let start = *s;
+                let (address, values) = line(s, "memory", |s| {
+                    let address = s.int::<usize>()?;
+                    s.literal(": ")?;
+                    Ok((address, s.separated(", ", |s| s.int::<D::Word>())?))
+                })?;
+                match address.checked_add(values.len()) {
+                    Some(end) if end <= size => {}
+                    _ => return start.error(format!("memory within the size of {}", size)),
+                }
+                for (address, value) in (address..).zip(values) {
+                    match memory.load_mut(address) {
+                        Some(cell) => *cell = value,
+                        None => return start.error(format!("memory within the size of {}", size)),
+                    }
+                }
+            }
+
+            Ok(Snapshot {
+                data: memory,
+                ip,
+                relative_base,
+                state,
+                inputs: inputs.into(),
+            })
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Snapshot<D>, anyhow::Error> {
+        let file = read_input(path)?;
+        Ok(Snapshot::parse(&file.contents)
+            .map_err(|e: ParseError| e.locate(&file.source, &file.contents, 1))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{assemble, InterruptReason, PagedMemory, VMStatus};
+
+    // Echoes back each input plus the one before it, keeping its state at the relative base
+    const ADDER: &str = "
+            arb #100
+        loop:
+            in [rb+1]
+            add [rb+0], [rb+1], [rb+2]
+            out [rb+2]
+            add [rb+1], #0, [rb+0]
+            jt #1, #loop
+    ";
+
+    fn adder() -> IntcodeVM<PagedMemory> {
+        IntcodeVM::new(PagedMemory::from(assemble("adder", ADDER).unwrap()))
+    }
+
+    fn outputs<D: IntcodeMemory<Word = i64>>(vm: &mut IntcodeVM<D>, inputs: &[i64]) -> Vec<i64> {
+        let mut outputs = Vec::new();
+        for &i in inputs {
+            vm.push_input(i);
+        }
+        vm.run_collecting(&mut outputs).unwrap();
+        outputs
+    }
+
+    #[test]
+    fn test_fork_and_restore() {
+        let mut vm = adder();
+        assert_eq!(outputs(&mut vm, &[1, 2]), vec![1, 3]);
+
+        let mut fork = vm.fork();
+        assert_eq!(outputs(&mut fork, &[10]), vec![12]);
+        assert_eq!(outputs(&mut vm, &[20]), vec![22]);
+
+        let snapshot = vm.snapshot();
+        assert_eq!(outputs(&mut vm, &[5, 5]), vec![25, 10]);
+        vm.restore(&snapshot);
+        assert_eq!(outputs(&mut vm, &[5, 5]), vec![25, 10]);
+    }
+
+    #[test]
+    fn test_restore_forgets_loops() {
+        // Spins while the cell at 7 is set, then halts
+        let mut vm = IntcodeVM::new(vec![1105i64, 1, 3, 1005, 7, 0, 99, 0]);
+        vm.set_loop_detection(true);
+        let snapshot = vm.snapshot();
+        vm.data_mut()[7] = 1;
+        assert_eq!(vm.run().unwrap(), InterruptReason::InfiniteLoop);
+
+        vm.restore(&snapshot);
+        assert_eq!(vm.run().unwrap(), InterruptReason::Terminate);
+    }
+
+    #[test]
+    fn test_save_and_parse() {
+        let mut vm = adder();
+        vm.push_input(4);
+        assert_eq!(vm.run().unwrap(), InterruptReason::WaitingForOutput);
+        vm.push_input(7);
+
+        let mut text = Vec::new();
+        vm.snapshot().write(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert_eq!(
+            text,
+            "\
+intcode snapshot
+ip 8
+relative_base 100
+state waiting_for_output relative 2
+inputs 7
+size 256
+memory 0: 109, 100, 203, 1, 22201, 0, 1, 2, 204, 2, 21201, 1, 0, 0, 1105, 1, 2
+memory 101: 4, 4
+"
+        );
+
+        let mut restored = IntcodeVM::from(Snapshot::<PagedMemory>::parse(&text).unwrap());
+        assert_eq!(restored.state(), VMStatus::WaitingForOutput);
+        assert_eq!(restored.output().unwrap(), 4);
+        assert_eq!(outputs(&mut restored, &[1]), vec![11, 8]);
+        assert_eq!(restored.ip(), 2);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let text = "intcode snapshot\nip 0\nrelative_base 0\nstate waiting_for_input immediate 3\n";
+        let err = Snapshot::<Vec<i64>>::parse(text)
+            .err()
+            .unwrap()
+            .locate("snap", text, 1);
+        assert_eq!((err.line, err.column), (4, 35));
+        assert_eq!(err.message, "expected an operand that can be written to");
+
+        let text = "intcode snapshot\nip 0\nrelative_base 0\nstate ready\ninputs \nsize 2\nmemory 1: 5, 6\n";
+        let err = Snapshot::<Vec<i64>>::parse(text)
+            .err()
+            .unwrap()
+            .locate("snap", text, 1);
+        assert_eq!((err.line, err.column), (7, 1));
+        assert_eq!(err.message, "expected memory within the size of 2");
+
+        // A run that would end past the last address is out of bounds, not an overflow
+        let text = "intcode snapshot\nip 0\nrelative_base 0\nstate ready\ninputs \nsize 2\nmemory 18446744073709551615: 5, 6\n";
+        let err = Snapshot::<Vec<i64>>::parse(text)
+            .err()
+            .unwrap()
+            .locate("snap", text, 1);
+        assert_eq!((err.line, err.column), (7, 1));
+        assert_eq!(err.message, "expected memory within the size of 2");
+
+        // Sizes are only trusted as far as the memory could really be that big
+        let text =
+            "intcode snapshot\nip 0\nrelative_base 0\nstate ready\ninputs \nsize 4294967296\n";
+        let err = Snapshot::<Vec<i64>>::parse(text)
+            .err()
+            .unwrap()
+            .locate("snap", text, 1);
+        assert_eq!((err.line, err.column), (6, 1));
+        assert_eq!(err.message, "expected a size of at most 16777216");
+
+        // Paged memory only allocates what the snapshot wrote, however far out that is
+        let snapshot = Snapshot::<PagedMemory>::parse(text).unwrap();
+        assert_eq!(snapshot.data.extent(), 0);
+        let text = format!("{}memory 4294967294: 5, 6\n", text);
+        let snapshot = Snapshot::<PagedMemory>::parse(&text).unwrap();
+        assert_eq!(snapshot.data.load(4294967295), Some(6));
+        let text = text.replace("size 4294967296", "size 4294967297");
+        let err = Snapshot::<PagedMemory>::parse(&text)
+            .err()
+            .unwrap()
+            .locate("snap", &text, 1);
+        assert_eq!(err.message, "expected a size of at most 4294967296");
+    }
+}
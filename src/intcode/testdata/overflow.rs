@@ -0,0 +1,136 @@
+// Transpiled from overflow by `aoc intcode transpile`.
+use crate::intcode::{
+    Arithmetic, IntcodeError, IntcodeErrorKind, IntcodeMemory, IntcodeVM, InterruptReason, StepResult, VMStatus,
+};
+
+/// Runs the program until it halts or stops for input or output, exactly as `IntcodeVM::run`
+/// would. Compiled blocks run natively; input, output, jumps to addresses that weren't compiled
+/// and any code that has been changed since are left to the interpreter.
+pub fn run<D>(vm: &mut IntcodeVM<D>) -> Result<InterruptReason, IntcodeError>
+where
+    D: IntcodeMemory<Word = i64>,
+{
+    if !intact(vm.data()) {
+        return vm.run();
+    }
+    loop {
+        if vm.state() == VMStatus::Ready && is_block(vm.ip()) {
+            if let Exit::Modified = vm.run_native(native)? {
+                return vm.run();
+            }
+        }
+
+        let writes = match vm.data().load(vm.ip()) {
+            Some(instruction) => matches!(instruction % 100, 1 | 2 | 3 | 7 | 8),
+            None => false,
+        };
+        if let StepResult::Interrupt(reason) = vm.step()? {
+            return Ok(reason);
+        }
+        if writes && !intact(vm.data()) {
+            return vm.run();
+        }
+    }
+}
+
+enum Exit {
+    /// The instruction at the ip is one for the interpreter.
+    Step,
+    /// A compiled instruction was overwritten, so none of the native code can be trusted.
+    Modified,
+}
+
+fn load<D: IntcodeMemory<Word = i64>>(m: &D, a: i64) -> Result<i64, IntcodeErrorKind> {
+    match a {
+        a if a >= 0 => m.load(a as usize),
+        _ => None,
+    }
+    .ok_or(IntcodeErrorKind::OutOfBoundsDereference(a))
+}
+
+/// Writes `v` to `a`, returning whether that changed a compiled instruction.
+fn store<D>(m: &mut D, a: i64, v: i64) -> Result<bool, IntcodeErrorKind>
+where
+    D: IntcodeMemory<Word = i64>,
+{
+    let cell = match a {
+        a if a >= 0 => m.load_mut(a as usize),
+        _ => None,
+    }
+    .ok_or(IntcodeErrorKind::OutOfBoundsDereference(a))?;
+    let changed = *cell != v && is_code(a as usize);
+    *cell = v;
+    Ok(changed)
+}
+
+fn jump(target: i64) -> Result<usize, IntcodeErrorKind> {
+    match target {
+        t if t >= 0 => Ok(t as usize),
+        t => Err(IntcodeErrorKind::OutOfBoundsIp(t)),
+    }
+}
+
+/// Whether memory still holds the instructions that were compiled.
+fn intact<D: IntcodeMemory<Word = i64>>(m: &D) -> bool {
+    CODE.iter().all(|&(start, words)| {
+        let mut cells = (start..).map(|a| m.load(a));
+        words.iter().all(|&word| cells.next() == Some(Some(word)))
+    })
+}
+
+fn is_block(ip: usize) -> bool {
+    matches!(ip, 0 | 2 | 7 | 10)
+}
+
+fn is_code(a: usize) -> bool {
+    matches!(a, 0..=14)
+}
+
+fn native<D: IntcodeMemory<Word = i64>>(
+    ip: &mut usize,
+    rb: &mut i64,
+    m: &mut D,
+    _arithmetic: Arithmetic,
+) -> Result<Exit, IntcodeErrorKind> {
+    loop {
+        match *ip {
+            0 => {
+                // 0: in [100]
+                *ip = 0;
+                return Ok(Exit::Step);
+            }
+            2 => {
+                // 2: arb #9223372036854775807
+                *rb = rb.checked_add(9223372036854775807).ok_or(IntcodeErrorKind::Overflow)?;
+                // 4: jt [100], #10
+                *ip = if load(m, 100)? != 0 { 10 } else { 7 };
+            }
+            7 => {
+                // 7: arb #1
+                *rb = rb.checked_add(1).ok_or(IntcodeErrorKind::Overflow)?;
+                // 9: hlt
+                *ip = 9;
+                return Ok(Exit::Step);
+            }
+            10 => {
+                // 10: add [rb+1], #0, [101]
+                let v = load(m, rb.saturating_add(1))?;
+                store(m, 101, v)?;
+                // 14: hlt
+                *ip = 14;
+                return Ok(Exit::Step);
+            }
+            _ => return Ok(Exit::Step),
+        }
+    }
+}
+
+const CODE: &[(usize, &[i64])] = &[
+    (
+        0,
+        &[
+            3, 100, 109, 9223372036854775807, 1005, 100, 10, 109, 1, 99, 1201, 1,
+            0, 101, 99,
+        ],
+    ),
+];
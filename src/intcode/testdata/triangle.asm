@@ -0,0 +1,20 @@
+; Outputs the triangle number of each input, working it out in a subroutine
+    arb #100
+loop:
+    in [rb+1]
+    jf [rb+1], #done
+    add #ret, #0, [rb+0]
+    jt #1, #triangle
+ret:
+    out [rb+2]
+    jt #1, #loop
+done:
+    hlt
+
+triangle:
+    add #0, #0, [rb+2]
+next:
+    add [rb+2], [rb+1], [rb+2]
+    add [rb+1], #-1, [rb+1]
+    jt [rb+1], #next
+    jt #1, [rb+0]
@@ -0,0 +1,179 @@
+// Transpiled from triangle by `aoc intcode transpile`.
+use crate::intcode::{
+    Arithmetic, IntcodeError, IntcodeErrorKind, IntcodeMemory, IntcodeVM, InterruptReason, StepResult, VMStatus,
+};
+
+/// Runs the program until it halts or stops for input or output, exactly as `IntcodeVM::run`
+/// would. Compiled blocks run natively; input, output, jumps to addresses that weren't compiled
+/// and any code that has been changed since are left to the interpreter.
+pub fn run<D>(vm: &mut IntcodeVM<D>) -> Result<InterruptReason, IntcodeError>
+where
+    D: IntcodeMemory<Word = i64>,
+{
+    if !intact(vm.data()) {
+        return vm.run();
+    }
+    loop {
+        if vm.state() == VMStatus::Ready && is_block(vm.ip()) {
+            if let Exit::Modified = vm.run_native(native)? {
+                return vm.run();
+            }
+        }
+
+        let writes = match vm.data().load(vm.ip()) {
+            Some(instruction) => matches!(instruction % 100, 1 | 2 | 3 | 7 | 8),
+            None => false,
+        };
+        if let StepResult::Interrupt(reason) = vm.step()? {
+            return Ok(reason);
+        }
+        if writes && !intact(vm.data()) {
+            return vm.run();
+        }
+    }
+}
+
+enum Exit {
+    /// The instruction at the ip is one for the interpreter.
+    Step,
+    /// A compiled instruction was overwritten, so none of the native code can be trusted.
+    Modified,
+}
+
+fn load<D: IntcodeMemory<Word = i64>>(m: &D, a: i64) -> Result<i64, IntcodeErrorKind> {
+    match a {
+        a if a >= 0 => m.load(a as usize),
+        _ => None,
+    }
+    .ok_or(IntcodeErrorKind::OutOfBoundsDereference(a))
+}
+
+/// Writes `v` to `a`, returning whether that changed a compiled instruction.
+fn store<D>(m: &mut D, a: i64, v: i64) -> Result<bool, IntcodeErrorKind>
+where
+    D: IntcodeMemory<Word = i64>,
+{
+    let cell = match a {
+        a if a >= 0 => m.load_mut(a as usize),
+        _ => None,
+    }
+    .ok_or(IntcodeErrorKind::OutOfBoundsDereference(a))?;
+    let changed = *cell != v && is_code(a as usize);
+    *cell = v;
+    Ok(changed)
+}
+
+fn jump(target: i64) -> Result<usize, IntcodeErrorKind> {
+    match target {
+        t if t >= 0 => Ok(t as usize),
+        t => Err(IntcodeErrorKind::OutOfBoundsIp(t)),
+    }
+}
+
+/// Whether memory still holds the instructions that were compiled.
+fn intact<D: IntcodeMemory<Word = i64>>(m: &D) -> bool {
+    CODE.iter().all(|&(start, words)| {
+        let mut cells = (start..).map(|a| m.load(a));
+        words.iter().all(|&word| cells.next() == Some(Some(word)))
+    })
+}
+
+fn is_block(ip: usize) -> bool {
+    matches!(ip, 0 | 2 | 4 | 7 | 14 | 16 | 19 | 20 | 24 | 35)
+}
+
+fn is_code(a: usize) -> bool {
+    matches!(a, 0..=37)
+}
+
+fn native<D: IntcodeMemory<Word = i64>>(
+    ip: &mut usize,
+    rb: &mut i64,
+    m: &mut D,
+    arithmetic: Arithmetic,
+) -> Result<Exit, IntcodeErrorKind> {
+    loop {
+        match *ip {
+            0 => {
+                // 0: arb #100
+                *rb = rb.checked_add(100).ok_or(IntcodeErrorKind::Overflow)?;
+                *ip = 2;
+            }
+            2 => {
+                // 2: in [rb+1]
+                *ip = 2;
+                return Ok(Exit::Step);
+            }
+            4 => {
+                // 4: jf [rb+1], #19
+                *ip = if load(m, rb.saturating_add(1))? == 0 { 19 } else { 7 };
+            }
+            7 => {
+                // 7: add #14, #0, [rb+0]
+                let v = 14;
+                if store(m, *rb, v)? {
+                    *ip = 11;
+                    return Ok(Exit::Modified);
+                }
+                // 11: jt #1, #20
+                *ip = 20;
+            }
+            14 => {
+                // 14: out [rb+2]
+                *ip = 14;
+                return Ok(Exit::Step);
+            }
+            16 => {
+                // 16: jt #1, #2
+                *ip = 2;
+            }
+            19 => {
+                // 19: hlt
+                *ip = 19;
+                return Ok(Exit::Step);
+            }
+            20 => {
+                // 20: add #0, #0, [rb+2]
+                let v = 0;
+                if store(m, rb.saturating_add(2), v)? {
+                    *ip = 24;
+                    return Ok(Exit::Modified);
+                }
+                *ip = 24;
+            }
+            24 => {
+                // 24: add [rb+2], [rb+1], [rb+2]
+                let v = arithmetic.add(load(m, rb.saturating_add(2))?, load(m, rb.saturating_add(1))?)?;
+                if store(m, rb.saturating_add(2), v)? {
+                    *ip = 28;
+                    return Ok(Exit::Modified);
+                }
+                // 28: add [rb+1], #-1, [rb+1]
+                let v = arithmetic.add(load(m, rb.saturating_add(1))?, -1)?;
+                if store(m, rb.saturating_add(1), v)? {
+                    *ip = 32;
+                    return Ok(Exit::Modified);
+                }
+                // 32: jt [rb+1], #24
+                *ip = if load(m, rb.saturating_add(1))? != 0 { 24 } else { 35 };
+            }
+            35 => {
+                // 35: jt #1, [rb+0]
+                *ip = jump(load(m, *rb)?)?;
+            }
+            _ => return Ok(Exit::Step),
+        }
+    }
+}
+
+const CODE: &[(usize, &[i64])] = &[
+    (
+        0,
+        &[
+            109, 100, 203, 1, 1206, 1, 19, 21101, 14, 0, 0, 1105,
+            1, 20, 204, 2, 1105, 1, 2, 99, 21101, 0, 0, 2,
+            22201, 2, 1, 2, 21201, 1, -1, 1, 1205, 1, 24, 2105,
+            1, 0,
+        ],
+    ),
+];
@@ -0,0 +1,251 @@
+//! Entry points for the `intcode` subcommands, which work on any Intcode program rather than
+//! solving a particular day.
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+
+use super::{
+    assemble, disassemble, Arithmetic, Cfg, Debugger, IntcodeNetwork, IntcodeVM, InterruptReason,
+    NetworkOutcome, PagedMemory, Profile, Trace,
+};
+use crate::futil::{read_csints, read_input};
+
+pub fn disasm<P: AsRef<Path>>(input: P) -> Result<(), anyhow::Error> {
+    let program = read_csints(input)?;
+    for line in disassemble(&program) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+pub fn asm<P: AsRef<Path>>(input: P) -> Result<(), anyhow::Error> {
+    let file = read_input(input)?;
+    let program: Vec<String> = assemble(&file.source, &file.contents)?
+        .iter()
+        .map(|v| v.to_string())
+        .collect();
+    println!("{}", program.join(","));
+    Ok(())
+}
+
+/// Writes the program's control flow graph as Graphviz DOT to `output`, or prints it.
+pub fn cfg<P: AsRef<Path>>(input: P, output: Option<&Path>) -> Result<(), anyhow::Error> {
+    let cfg = Cfg::build(&read_csints(input)?);
+    match output {
+        Some(path) => {
+            let mut file = io::BufWriter::new(std::fs::File::create(path)?);
+            cfg.write_dot(&mut file)?;
+            file.flush()?;
+        }
+        None => cfg.write_dot(&mut io::stdout())?,
+    }
+    Ok(())
+}
+
+/// Prints the program as C-like pseudocode.
+pub fn decompile<P: AsRef<Path>>(input: P) -> Result<(), anyhow::Error> {
+    print!("{}", super::decompile(&read_csints(input)?));
+    Ok(())
+}
+
+/// Writes a Rust module that runs the program natively to `output`, or prints it.
+pub fn transpile<P: AsRef<Path>>(input: P, output: Option<&Path>) -> Result<(), anyhow::Error> {
+    let source = input.as_ref().display().to_string();
+    let program = read_csints(input)?;
+    let module = super::transpile(&program, &source);
+    match output {
+        Some(path) => std::fs::write(path, module)?,
+        None => print!("{}", module),
+    }
+    Ok(())
+}
+
+pub fn debug<P: AsRef<Path>>(input: P) -> Result<(), anyhow::Error> {
+    let program = read_csints(input)?;
+    let mut debugger = Debugger::new(IntcodeVM::new(PagedMemory::from(program)));
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+
+    debugger.show_location(&mut stdout)?;
+    let mut line = String::new();
+    loop {
+        write!(stdout, "(icdb) ")?;
+        stdout.flush()?;
+        line.clear();
+        if stdin.lock().read_line(&mut line)? == 0 || !debugger.command(&mut stdout, &line)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Runs the program on `feed`, writing a trace of every instruction to `output` if given, or
+/// otherwise printing the last `last` instructions executed once it stops. It can be made to
+/// give up after `fuel` instructions or once it's stuck in a loop, for programs that don't halt.
+pub fn trace<P: AsRef<Path>>(
+    input: P,
+    feed: &[i64],
+    output: Option<&Path>,
+    last: usize,
+    arithmetic: Arithmetic,
+    fuel: Option<usize>,
+    detect_loops: bool,
+) -> Result<(), anyhow::Error> {
+    let program = read_csints(input)?;
+    let mut vm = IntcodeVM::new(PagedMemory::from(program));
+    vm.set_arithmetic(arithmetic);
+    vm.set_loop_detection(detect_loops);
+    vm.set_trace(Some(match output {
+        Some(path) => Trace::file(path)?,
+        None => Trace::ring_buffer(last),
+    }));
+    for &v in feed {
+        vm.push_input(v);
+    }
+
+    let mut fuel = fuel.unwrap_or(usize::MAX);
+    let mut outputs = Vec::new();
+    let result = loop {
+        match vm.run_with_fuel(&mut fuel) {
+            Ok(InterruptReason::WaitingForOutput) => match vm.output() {
+                Ok(v) => outputs.push(v),
+                Err(e) => break Err(e),
+            },
+            result => break result,
+        }
+    };
+    if let Some(trace) = vm.trace() {
+        for entry in trace.entries() {
+            println!("{}", entry);
+        }
+        if let Some(e) = trace.error() {
+            return Err(anyhow!("Failed to write trace: {}", e));
+        }
+    }
+    let reason = result?;
+    println!("Output: {:?}", outputs);
+    match reason {
+        InterruptReason::WaitingForInput => return Err(anyhow!("Ran out of input")),
+        InterruptReason::OutOfFuel => println!("Out of fuel at ip {}", vm.ip()),
+        InterruptReason::InfiniteLoop => println!("Stuck in a loop at ip {}", vm.ip()),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Runs the program on `feed` and reports where it spent its time, optionally writing folded
+/// stacks for a flame graph to `folded`.
+pub fn profile<P: AsRef<Path>>(
+    input: P,
+    feed: &[i64],
+    folded: Option<&Path>,
+    top: usize,
+    arithmetic: Arithmetic,
+) -> Result<(), anyhow::Error> {
+    let program = read_csints(input)?;
+    let mut vm = IntcodeVM::new(PagedMemory::from(program));
+    vm.set_arithmetic(arithmetic);
+    vm.set_profile(Some(Profile::new()));
+
+    let result = vm.run_with_inputs(feed.iter().copied());
+    if let Some(profile) = vm.profile() {
+        profile.report(&mut io::stdout(), &vm, top)?;
+        if let Some(path) = folded {
+            let mut file = io::BufWriter::new(std::fs::File::create(path)?);
+            profile.write_folded(&mut file)?;
+            file.flush()?;
+        }
+    }
+    println!("Output: {:?}", result?);
+    Ok(())
+}
+
+/// Runs one copy of the program per `setup` value, each starting with its value as input, with
+/// every copy's output feeding the next and the last one's going back to the first if
+/// `feedback` is set. `feed` goes to the first copy after its setup value.
+pub fn chain<P: AsRef<Path>>(
+    input: P,
+    setup: &[i64],
+    feed: &[i64],
+    feedback: bool,
+) -> Result<(), anyhow::Error> {
+    if setup.is_empty() {
+        return Err(anyhow!("Nothing to chain without any setup values"));
+    }
+    let program = PagedMemory::from(read_csints(input)?);
+    let mut net = IntcodeNetwork::new();
+    for &v in setup {
+        let vm = net.add(IntcodeVM::new(program.clone()));
+        net.push_input(vm, v);
+    }
+    for vm in 1..setup.len() {
+        net.connect(vm - 1, vm);
+    }
+    let last = setup.len() - 1;
+    if feedback {
+        net.connect(last, 0);
+    }
+    for &v in feed {
+        net.push_input(0, v);
+    }
+
+    let outcome = net.run()?;
+    match feedback {
+        true => println!("Last output: {:?}", net.last_output(last)),
+        false => println!("Output: {:?}", net.outputs()),
+    }
+    match outcome {
+        NetworkOutcome::Terminated => Ok(()),
+        NetworkOutcome::Deadlock(waiting) => Err(anyhow!(
+            "Deadlocked: copies {:?} are waiting for input",
+            waiting
+        )),
+        NetworkOutcome::InfiniteLoop(looping) => Err(anyhow!(
+            "Copies {:?} are stuck in an infinite loop",
+            looping
+        )),
+    }
+}
+
+/// Runs the program on `feed` `runs` times through the plain interpreter and `runs` times with
+/// the decode cache on, and prints how long a run took on average each way.
+pub fn bench<P: AsRef<Path>>(
+    input: P,
+    feed: &[i64],
+    runs: u32,
+    arithmetic: Arithmetic,
+) -> Result<(), anyhow::Error> {
+    let program = PagedMemory::from(read_csints(input)?);
+    let runs = runs.max(1);
+    let time = |cached: bool| -> Result<(Vec<i64>, Duration), anyhow::Error> {
+        let mut outputs = Vec::new();
+        let start = Instant::now();
+        for _ in 0..runs {
+            let mut vm = IntcodeVM::new(program.clone());
+            vm.set_decode_cache(cached);
+            vm.set_arithmetic(arithmetic);
+            outputs = vm.run_with_inputs(feed.iter().copied())?;
+        }
+        Ok((outputs, start.elapsed() / runs))
+    };
+
+    let (expected, interpreted) = time(false)?;
+    let (outputs, cached) = time(true)?;
+    if outputs != expected {
+        return Err(anyhow!(
+            "Decode cache changed the output from {:?} to {:?}",
+            expected,
+            outputs
+        ));
+    }
+    println!("Output: {:?}", expected);
+    println!("interpreter:  {:>10.3?} per run", interpreted);
+    println!(
+        "decode cache: {:>10.3?} per run ({:.2}x)",
+        cached,
+        interpreted.as_secs_f64() / cached.as_secs_f64().max(1e-9)
+    );
+    Ok(())
+}
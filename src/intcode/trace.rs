@@ -0,0 +1,274 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use super::disasm::{decode_with, Instruction, Param};
+use super::{read_index, IntcodeErrorKind, IntcodeMemory, IntcodeVM, StepResult, Word};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryWrite<W = i64> {
+    pub address: usize,
+    pub old: W,
+    pub new: W,
+}
+
+/// One executed instruction, as the VM saw it just before running it.
+#[derive(Debug, PartialEq)]
+pub struct TraceEntry<W = i64> {
+    pub ip: usize,
+    pub relative_base: i64,
+    pub instruction: W,
+    /// `None` when the instruction word isn't one the VM can execute. Immediates too wide for an
+    /// i64 are clamped here, but `operands` has them in full.
+    pub decoded: Option<Instruction>,
+    /// The values the instruction read, followed by the addresses it writes to.
+    pub operands: Vec<W>,
+    pub writes: Vec<MemoryWrite<W>>,
+}
+
+impl<W: Word> TraceEntry<W> {
+    pub(super) fn before<D>(vm: &IntcodeVM<D>, instruction: W) -> TraceEntry<W>
+    where
+        D: IntcodeMemory<Word = W>,
+    {
+        let words: Vec<i64> = (vm.ip..vm.ip + 4)
+            .map_while(|a| vm.data.load(a).map(|w| w.clamp_to_i64()))
+            .collect();
+        let decoded = decode_with(&words, 0, |code| vm.opcode_info(code)).map(|mut decoded| {
+            decoded.address = vm.ip;
+            decoded
+        });
+
+        let mut operands = Vec::new();
+        let mut writes = Vec::new();
+        if let Some(decoded) = &decoded {
+            for (i, param) in decoded.params.iter().enumerate() {
+                let address = match *param {
+                    Param::Immediate(_) => {
+                        operands.push(vm.data.load(vm.ip + 1 + i).unwrap_or_default());
+                        continue;
+                    }
+                    Param::Position(a) => a,
+                    Param::Relative(a) => vm.relative_base.saturating_add(a),
+                };
+                let value = read_index(&vm.data, address).unwrap_or_default();
+                if i < decoded.opcode.inputs {
+                    operands.push(value);
+                } else {
+                    operands.push(W::from_i64(address));
+                    if address >= 0 {
+                        writes.push(MemoryWrite {
+                            address: address as usize,
+                            old: value.clone(),
+                            new: value,
+                        });
+                    }
+                }
+            }
+        }
+
+        TraceEntry {
+            ip: vm.ip,
+            relative_base: vm.relative_base,
+            instruction,
+            decoded,
+            operands,
+            writes,
+        }
+    }
+
+    /// Fills in what the instruction wrote. Instructions that failed or were interrupted before
+    /// storing anything are recorded without writes.
+    pub(super) fn after<D: IntcodeMemory<Word = W>>(
+        &mut self,
+        vm: &IntcodeVM<D>,
+        result: &Result<StepResult, IntcodeErrorKind>,
+    ) {
+        match result {
+            Ok(StepResult::Continue) | Ok(StepResult::Jump) => {
+                for write in self.writes.iter_mut() {
+                    write.new = vm
+                        .data
+                        .load(write.address)
+                        .unwrap_or_else(|| write.old.clone());
+                }
+            }
+            _ => self.writes.clear(),
+        }
+    }
+}
+
+impl<W: Word> fmt::Display for TraceEntry<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match &self.decoded {
+            Some(decoded) => decoded.to_string(),
+            None => format!("data {}", self.instruction),
+        };
+        let operands: Vec<String> = self.operands.iter().map(|v| v.to_string()).collect();
+        write!(
+            f,
+            "{:>5}: {:<24} rb={} ({})",
+            self.ip,
+            text,
+            self.relative_base,
+            operands.join(", ")
+        )?;
+        for write in &self.writes {
+            write!(f, " [{}] {} -> {}", write.address, write.old, write.new)?;
+        }
+        Ok(())
+    }
+}
+
+enum Sink<W> {
+    Buffer {
+        entries: VecDeque<TraceEntry<W>>,
+        capacity: usize,
+    },
+    Writer(Box<dyn Write + Send>),
+}
+
+/// Where a tracing VM sends the instructions it executes: either a ring buffer holding the most
+/// recent ones, or a writer that gets every instruction as a line of text.
+pub struct Trace<W = i64> {
+    sink: Sink<W>,
+    error: Option<io::Error>,
+}
+
+impl<W: Word> Trace<W> {
+    pub fn ring_buffer(capacity: usize) -> Trace<W> {
+        Trace {
+            sink: Sink::Buffer {
+                entries: VecDeque::with_capacity(capacity),
+                capacity,
+            },
+            error: None,
+        }
+    }
+
+    pub fn writer<O: Write + Send + 'static>(writer: O) -> Trace<W> {
+        Trace {
+            sink: Sink::Writer(Box::new(writer)),
+            error: None,
+        }
+    }
+
+    pub fn file<P: AsRef<Path>>(path: P) -> io::Result<Trace<W>> {
+        Ok(Trace::writer(BufWriter::new(File::create(path)?)))
+    }
+
+    /// The buffered entries, oldest first. Always empty when tracing to a writer.
+    pub fn entries(&self) -> impl Iterator<Item = &TraceEntry<W>> {
+        let entries = match &self.sink {
+            Sink::Buffer { entries, .. } => Some(entries.iter()),
+            Sink::Writer(_) => None,
+        };
+        entries.into_iter().flatten()
+    }
+
+    /// The first error the writer returned. Nothing more is written after one.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    pub(super) fn record(&mut self, entry: TraceEntry<W>) {
+        match &mut self.sink {
+            Sink::Buffer { entries, capacity } => {
+                if *capacity == 0 {
+                    return;
+                }
+                if entries.len() == *capacity {
+                    entries.pop_front();
+                }
+                entries.push_back(entry);
+            }
+            Sink::Writer(w) => {
+                if self.error.is_none() {
+                    if let Err(e) = writeln!(w, "{}", entry) {
+                        self.error = Some(e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{assemble, PagedMemory};
+
+    fn traced(source: &str, inputs: Vec<i64>, trace: Trace) -> IntcodeVM<PagedMemory> {
+        let program = assemble("test", source).unwrap();
+        let mut vm = IntcodeVM::new(PagedMemory::from(program));
+        vm.set_trace(Some(trace));
+        let _ = vm.run_with_inputs(inputs);
+        vm
+    }
+
+    #[test]
+    fn test_ring_buffer() {
+        let vm = traced(
+            "
+                in [9]
+                add [9], #-2, [rb+10]
+                out [10]
+                hlt
+                data 0
+            ",
+            vec![7],
+            Trace::ring_buffer(3),
+        );
+        let lines: Vec<String> = vm
+            .trace()
+            .unwrap()
+            .entries()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "    2: add [9], #-2, [rb+10]    rb=0 (7, -2, 10) [10] 0 -> 5",
+                "    6: out [10]                 rb=0 (5)",
+                "    8: hlt                      rb=0 ()",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_trace_up_to_fault() {
+        let vm = traced(
+            "
+                add #1, #2, [100]
+                arb #-5
+                out [rb+1]
+            ",
+            vec![],
+            Trace::ring_buffer(10),
+        );
+        let entries: Vec<&TraceEntry> = vm.trace().unwrap().entries().collect();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            entries[0].writes,
+            vec![MemoryWrite {
+                address: 100,
+                old: 0,
+                new: 3
+            }]
+        );
+        assert_eq!((entries[2].ip, entries[2].relative_base), (6, -5));
+    }
+
+    #[test]
+    fn test_trace_file() {
+        let path = std::env::temp_dir().join("aoc_intcode_trace_test.txt");
+        let vm = traced("out #42\nhlt", vec![], Trace::file(&path).unwrap());
+        drop(vm);
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(text.lines().count(), 2);
+        assert!(text.starts_with("    0: out #42"));
+    }
+}
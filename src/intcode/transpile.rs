@@ -0,0 +1,472 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use super::cfg::{always_jumps, is_jump, Cfg};
+use super::disasm::{Instruction, Param};
+use super::{Arithmetic, IntcodeError, IntcodeErrorKind, IntcodeMemory, IntcodeVM};
+
+impl<D: IntcodeMemory<Word = i64>> IntcodeVM<D> {
+    /// Runs `f`, a transpiled program's native code, directly on the VM's ip, relative base and
+    /// memory, with its arithmetic policy. The VM faults if it fails, as it would have done in
+    /// the interpreter.
+    pub fn run_native<T, F>(&mut self, f: F) -> Result<T, IntcodeError>
+    where
+        F: FnOnce(&mut usize, &mut i64, &mut D, Arithmetic) -> Result<T, IntcodeErrorKind>,
+    {
+        self.check_fault()?;
+        if let Some(cache) = &mut self.cache {
+            cache.clear();
+        }
+        if let Some(loops) = &mut self.loops {
+            loops.reset();
+        }
+        let result = f(
+            &mut self.ip,
+            &mut self.relative_base,
+            &mut self.data,
+            self.arithmetic,
+        );
+        self.fault(result)
+    }
+}
+
+/// What static analysis could find of a program: every instruction reachable from the entry
+/// point, and which of them start a basic block.
+struct Code {
+    instructions: BTreeMap<usize, Instruction>,
+    blocks: BTreeSet<usize>,
+}
+
+/// Takes the code from the control flow graph, starting a block after every `in` and `out` as
+/// well, since native code hands I/O to the interpreter and picks up again after it.
+fn find_code(program: &[i64]) -> Code {
+    let mut instructions = BTreeMap::new();
+    let mut blocks = BTreeSet::new();
+    for block in Cfg::build(program).blocks() {
+        blocks.insert(block.start);
+        for instruction in &block.instructions {
+            if matches!(instruction.opcode.mnemonic, "in" | "out") {
+                blocks.insert(instruction.address + instruction.size());
+            }
+            instructions.insert(instruction.address, instruction.clone());
+        }
+    }
+
+    // An `in` or `out` can be the last thing before data
+    blocks.retain(|a| instructions.contains_key(a));
+    Code {
+        instructions,
+        blocks,
+    }
+}
+
+/// The address `offset` from the relative base, which saturates as the interpreter's does, so
+/// that an address off either end is out of bounds rather than an overflow.
+fn relative(offset: i64) -> String {
+    match offset {
+        0 => "*rb".to_string(),
+        o if o > 0 => format!("rb.saturating_add({})", o),
+        o => match o.checked_neg() {
+            Some(o) => format!("rb.saturating_sub({})", o),
+            None => format!("rb.saturating_add({})", o),
+        },
+    }
+}
+
+/// The value of an input parameter, as a Rust expression.
+fn read(param: Param) -> String {
+    match param {
+        Param::Immediate(v) => v.to_string(),
+        Param::Position(a) => format!("load(m, {})?", a),
+        Param::Relative(o) => format!("load(m, {})?", relative(o)),
+    }
+}
+
+/// The address an output parameter writes to, as a Rust expression.
+fn address(param: Param) -> String {
+    match param {
+        Param::Position(a) => a.to_string(),
+        Param::Relative(o) => relative(o),
+        Param::Immediate(_) => unreachable!("outputs are never immediate"),
+    }
+}
+
+/// The value an arithmetic or comparison instruction stores, folding constants and the
+/// identities compiled Intcode uses to move values about. Constants are only folded when they
+/// can't overflow, so the VM's arithmetic policy applies to everything else.
+fn value(instruction: &Instruction) -> String {
+    let (a, b) = (instruction.params[0], instruction.params[1]);
+    let constant = match (a, b) {
+        (Param::Immediate(x), Param::Immediate(y)) => Some((x, y)),
+        _ => None,
+    };
+    match (instruction.opcode.mnemonic, constant) {
+        ("add", Some((x, y))) if x.checked_add(y).is_some() => (x + y).to_string(),
+        ("mul", Some((x, y))) if x.checked_mul(y).is_some() => (x * y).to_string(),
+        ("lt", Some((x, y))) => i64::from(x < y).to_string(),
+        ("eq", Some((x, y))) => i64::from(x == y).to_string(),
+        ("add", _) if a == Param::Immediate(0) => read(b),
+        ("add", _) if b == Param::Immediate(0) => read(a),
+        ("mul", _) if a == Param::Immediate(1) => read(b),
+        ("mul", _) if b == Param::Immediate(1) => read(a),
+        ("add", _) => format!("arithmetic.add({}, {})?", read(a), read(b)),
+        ("mul", _) => format!("arithmetic.mul({}, {})?", read(a), read(b)),
+        ("lt", _) => format!("i64::from({} < {})", read(a), read(b)),
+        _ => format!("i64::from({} == {})", read(a), read(b)),
+    }
+}
+
+/// Whether a write to `param` could land on a compiled instruction.
+fn may_write_code(code: &Code, param: Param) -> bool {
+    match param {
+        Param::Position(a) => a >= 0 && code_words(code).any(|w| w == a as usize),
+        _ => true,
+    }
+}
+
+fn code_words(code: &Code) -> impl Iterator<Item = usize> + '_ {
+    code.instructions
+        .values()
+        .flat_map(|i| i.address..i.address + i.size())
+}
+
+/// Runs of consecutive addresses holding compiled instructions, as (first, last) pairs.
+fn code_ranges(code: &Code) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for instruction in code.instructions.values() {
+        let (start, end) = (
+            instruction.address,
+            instruction.address + instruction.size() - 1,
+        );
+        match ranges.last_mut() {
+            Some(last) if last.1 + 1 >= start => last.1 = last.1.max(end),
+            _ => ranges.push((start, end)),
+        }
+    }
+    ranges
+}
+
+fn pattern<I: IntoIterator<Item = String>>(alternatives: I) -> String {
+    alternatives
+        .into_iter()
+        .collect::<Vec<String>>()
+        .join(" | ")
+}
+
+const WORDS_PER_LINE: usize = 12;
+
+// Everything in a transpiled module besides the blocks of native code, which go in `native`.
+const RUNTIME: &str = r#"
+/// Runs the program until it halts or stops for input or output, exactly as `IntcodeVM::run`
+/// would. Compiled blocks run natively; input, output, jumps to addresses that weren't compiled
+/// and any code that has been changed since are left to the interpreter.
+pub fn run<D>(vm: &mut IntcodeVM<D>) -> Result<InterruptReason, IntcodeError>
+where
+    D: IntcodeMemory<Word = i64>,
+{
+    if !intact(vm.data()) {
+        return vm.run();
+    }
+    loop {
+        if vm.state() == VMStatus::Ready && is_block(vm.ip()) {
+            if let Exit::Modified = vm.run_native(native)? {
+                return vm.run();
+            }
+        }
+
+        let writes = match vm.data().load(vm.ip()) {
+            Some(instruction) => matches!(instruction % 100, 1 | 2 | 3 | 7 | 8),
+            None => false,
+        };
+        if let StepResult::Interrupt(reason) = vm.step()? {
+            return Ok(reason);
+        }
+        if writes && !intact(vm.data()) {
+            return vm.run();
+        }
+    }
+}
+
+enum Exit {
+    /// The instruction at the ip is one for the interpreter.
+    Step,
+    /// A compiled instruction was overwritten, so none of the native code can be trusted.
+    Modified,
+}
+
+fn load<D: IntcodeMemory<Word = i64>>(m: &D, a: i64) -> Result<i64, IntcodeErrorKind> {
+    match a {
+        a if a >= 0 => m.load(a as usize),
+        _ => None,
+    }
+    .ok_or(IntcodeErrorKind::OutOfBoundsDereference(a))
+}
+
+/// Writes `v` to `a`, returning whether that changed a compiled instruction.
+fn store<D>(m: &mut D, a: i64, v: i64) -> Result<bool, IntcodeErrorKind>
+where
+    D: IntcodeMemory<Word = i64>,
+{
+    let cell = match a {
+        a if a >= 0 => m.load_mut(a as usize),
+        _ => None,
+    }
+    .ok_or(IntcodeErrorKind::OutOfBoundsDereference(a))?;
+    let changed = *cell != v && is_code(a as usize);
+    *cell = v;
+    Ok(changed)
+}
+
+fn jump(target: i64) -> Result<usize, IntcodeErrorKind> {
+    match target {
+        t if t >= 0 => Ok(t as usize),
+        t => Err(IntcodeErrorKind::OutOfBoundsIp(t)),
+    }
+}
+
+/// Whether memory still holds the instructions that were compiled.
+fn intact<D: IntcodeMemory<Word = i64>>(m: &D) -> bool {
+    CODE.iter().all(|&(start, words)| {
+        let mut cells = (start..).map(|a| m.load(a));
+        words.iter().all(|&word| cells.next() == Some(Some(word)))
+    })
+}
+"#;
+
+/// Translates `program` into the source of a Rust module whose `run` function behaves like
+/// `IntcodeVM::run`. Code is found by following the program from its entry point, and jumps
+/// through memory are resolved at run time against the blocks found that way. `source` names
+/// the program in the module's doc comment.
+///
+/// Native code reports errors with the ip at the start of the block it was running.
+pub fn transpile(program: &[i64], source: &str) -> String {
+    let code = find_code(program);
+    let uses_rb = code
+        .instructions
+        .values()
+        .any(|i| i.params.iter().any(|p| matches!(p, Param::Relative(_))));
+    let uses_arithmetic = code
+        .instructions
+        .values()
+        .any(|i| matches!(i.opcode.mnemonic, "add" | "mul") && value(i).starts_with("arithmetic"));
+
+    let mut out = String::new();
+    let o = &mut out;
+    // Writing to a String can't fail
+    let _ = writeln!(
+        o,
+        "// Transpiled from {} by `aoc intcode transpile`.",
+        source
+    );
+    let _ = writeln!(
+        o,
+        "use crate::intcode::{{\n    Arithmetic, IntcodeError, IntcodeErrorKind, IntcodeMemory, \
+         IntcodeVM, InterruptReason, StepResult, VMStatus,\n}};"
+    );
+    o.push_str(RUNTIME);
+
+    let blocks = code.blocks.iter().map(|b| b.to_string());
+    let _ = writeln!(
+        o,
+        "\nfn is_block(ip: usize) -> bool {{\n    matches!(ip, {})\n}}",
+        pattern(blocks)
+    );
+    let ranges = code_ranges(&code)
+        .into_iter()
+        .map(|(start, end)| match start == end {
+            true => start.to_string(),
+            false => format!("{}..={}", start, end),
+        });
+    let _ = writeln!(
+        o,
+        "\nfn is_code(a: usize) -> bool {{\n    matches!(a, {})\n}}",
+        pattern(ranges)
+    );
+
+    let _ = writeln!(
+        o,
+        "\nfn native<D: IntcodeMemory<Word = i64>>(\n    ip: &mut usize,\n    {}: &mut i64,\n    \
+         m: &mut D,\n    {}: Arithmetic,\n) -> Result<Exit, IntcodeErrorKind> {{",
+        if uses_rb { "rb" } else { "_rb" },
+        if uses_arithmetic {
+            "arithmetic"
+        } else {
+            "_arithmetic"
+        }
+    );
+    let _ = writeln!(o, "    loop {{\n        match *ip {{");
+    for &start in &code.blocks {
+        let _ = writeln!(o, "            {} => {{", start);
+        let mut address = start;
+        while let Some(instruction) = code.instructions.get(&address) {
+            write_instruction(o, &code, instruction);
+            address += instruction.size();
+            if !falls_through(instruction) {
+                break;
+            }
+            if code.blocks.contains(&address) || !code.instructions.contains_key(&address) {
+                let _ = writeln!(o, "                *ip = {};", address);
+                break;
+            }
+        }
+        let _ = writeln!(o, "            }}");
+    }
+    let _ = writeln!(o, "            _ => return Ok(Exit::Step),");
+    let _ = writeln!(o, "        }}\n    }}\n}}");
+
+    let _ = writeln!(o, "\nconst CODE: &[(usize, &[i64])] = &[");
+    for (start, end) in code_ranges(&code) {
+        let _ = writeln!(o, "    (\n        {},\n        &[", start);
+        for line in program[start..=end].chunks(WORDS_PER_LINE) {
+            let words: Vec<String> = line.iter().map(|w| w.to_string()).collect();
+            let _ = writeln!(o, "            {},", words.join(", "));
+        }
+        let _ = writeln!(o, "        ],\n    ),");
+    }
+    let _ = writeln!(o, "];");
+    out
+}
+
+/// Whether execution carries on into the next instruction within the same block.
+fn falls_through(instruction: &Instruction) -> bool {
+    !matches!(instruction.opcode.mnemonic, "hlt" | "in" | "out") && !is_jump(instruction)
+}
+
+fn write_instruction(o: &mut String, code: &Code, instruction: &Instruction) {
+    let indent = "                ";
+    let _ = writeln!(o, "{}// {}: {}", indent, instruction.address, instruction);
+    let next = instruction.address + instruction.size();
+    let params = &instruction.params;
+    match instruction.opcode.mnemonic {
+        "add" | "mul" | "lt" | "eq" => {
+            let _ = writeln!(o, "{}let v = {};", indent, value(instruction));
+            let store = format!("store(m, {}, v)?", address(params[2]));
+            if may_write_code(code, params[2]) {
+                let _ = writeln!(o, "{}if {} {{", indent, store);
+                let _ = writeln!(o, "{}    *ip = {};", indent, next);
+                let _ = writeln!(o, "{}    return Ok(Exit::Modified);", indent);
+                let _ = writeln!(o, "{}}}", indent);
+            } else {
+                let _ = writeln!(o, "{}{};", indent, store);
+            }
+        }
+        "arb" => {
+            let _ = writeln!(
+                o,
+                "{}*rb = rb.checked_add({}).ok_or(IntcodeErrorKind::Overflow)?;",
+                indent,
+                read(params[0])
+            );
+        }
+        "jt" | "jf" => {
+            let target = match params[1] {
+                Param::Immediate(t) if t >= 0 => t.to_string(),
+                p => format!("jump({})?", read(p)),
+            };
+            if always_jumps(instruction) {
+                let _ = writeln!(o, "{}*ip = {};", indent, target);
+            } else if let Param::Immediate(_) = params[0] {
+                let _ = writeln!(o, "{}*ip = {};", indent, next);
+            } else {
+                let test = if instruction.opcode.mnemonic == "jt" {
+                    "!="
+                } else {
+                    "=="
+                };
+                let _ = writeln!(
+                    o,
+                    "{}*ip = if {} {} 0 {{ {} }} else {{ {} }};",
+                    indent,
+                    read(params[0]),
+                    test,
+                    target,
+                    next
+                );
+            }
+        }
+        _ => {
+            let _ = writeln!(o, "{}*ip = {};", indent, instruction.address);
+            let _ = writeln!(o, "{}return Ok(Exit::Step);", indent);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{assemble, InterruptReason, PagedMemory, TRIANGLE};
+
+    // What `aoc intcode transpile` makes of TRIANGLE, compiled in so it can be run
+    mod triangle {
+        include!("testdata/triangle.rs");
+    }
+
+    // Moves the relative base to i64::MAX, then either moves it one further or reads just past
+    // it, as the input says
+    const OVERFLOW: [i64; 15] = [
+        3, 100, 109, i64::MAX, 1005, 100, 10, 109, 1, 99, 1201, 1, 0, 101, 99,
+    ];
+
+    // Never writes its own code or jumps through memory, so parts of the runtime go unused
+    #[allow(dead_code)]
+    mod overflow {
+        include!("testdata/overflow.rs");
+    }
+
+    fn run_transpiled(program: Vec<i64>, inputs: &[i64]) -> Vec<i64> {
+        let mut vm = IntcodeVM::new(PagedMemory::from(program));
+        for &i in inputs {
+            vm.push_input(i);
+        }
+        let mut outputs = Vec::new();
+        loop {
+            match triangle::run(&mut vm).unwrap() {
+                InterruptReason::WaitingForOutput => outputs.push(vm.output().unwrap()),
+                InterruptReason::Terminate => return outputs,
+                reason => panic!("stopped with {:?}", reason),
+            }
+        }
+    }
+
+    #[test]
+    fn test_transpile() {
+        let program = assemble("triangle.asm", TRIANGLE).unwrap();
+        assert_eq!(
+            transpile(&program, "triangle"),
+            include_str!("testdata/triangle.rs")
+        );
+        assert_eq!(run_transpiled(program, &[3, 10, 0]), vec![6, 55]);
+    }
+
+    #[test]
+    fn test_relative_base_overflow() {
+        assert_eq!(
+            transpile(&OVERFLOW, "overflow"),
+            include_str!("testdata/overflow.rs")
+        );
+        for &(input, kind) in &[
+            (0, IntcodeErrorKind::Overflow),
+            (1, IntcodeErrorKind::OutOfBoundsDereference(i64::MAX)),
+        ] {
+            let mut interpreted = IntcodeVM::new(PagedMemory::from(&OVERFLOW[..]));
+            interpreted.push_input(input);
+            assert_eq!(interpreted.run().unwrap_err().kind, kind);
+
+            let mut native = IntcodeVM::new(PagedMemory::from(&OVERFLOW[..]));
+            native.push_input(input);
+            assert_eq!(overflow::run(&mut native).unwrap_err().kind, kind);
+        }
+    }
+
+    #[test]
+    fn test_changed_code_is_interpreted() {
+        // Start the sum at 1000 instead of 0
+        let mut program = assemble("triangle.asm", TRIANGLE).unwrap();
+        program[21] = 1000;
+        let expected = IntcodeVM::new(PagedMemory::from(program.clone()))
+            .run_with_inputs(vec![3, 10, 0])
+            .unwrap();
+        assert_eq!(expected, vec![1006, 1055]);
+        assert_eq!(run_transpiled(program, &[3, 10, 0]), expected);
+    }
+}
@@ -0,0 +1,145 @@
+use std::convert::TryFrom;
+use std::fmt::{Debug, Display};
+use std::hash::Hash;
+use std::str::FromStr;
+
+use num_bigint::BigInt;
+
+use super::Arithmetic;
+
+/// A number a memory cell can hold. Addresses, opcodes and the relative base are always i64, so
+/// a word only needs to convert to one when it's used as one of those.
+pub trait Word: Clone + Debug + Default + Display + Eq + Hash + Ord + FromStr + 'static {
+    fn from_i64(v: i64) -> Self;
+
+    /// `None` when the word is out of an i64's range.
+    fn to_i64(&self) -> Option<i64>;
+
+    /// `a + b` or `a * b` under `arithmetic`, or `None` when a checked operation overflows.
+    fn add_with(&self, other: &Self, arithmetic: Arithmetic) -> Option<Self>;
+    fn mul_with(&self, other: &Self, arithmetic: Arithmetic) -> Option<Self>;
+
+    /// The word as an i64, clamped to its range. A word that doesn't fit is no good as an
+    /// address or opcode, and clamping keeps it that way while still saying which side it's on.
+    fn clamp_to_i64(&self) -> i64 {
+        match self.to_i64() {
+            Some(v) => v,
+            None if *self < Self::default() => i64::MIN,
+            None => i64::MAX,
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+macro_rules! primitive_word {
+    ($t:ty) => {
+        impl Word for $t {
+            #[inline]
+            fn from_i64(v: i64) -> Self {
+                <$t>::from(v)
+            }
+
+            #[inline]
+            fn to_i64(&self) -> Option<i64> {
+                i64::try_from(*self).ok()
+            }
+
+            #[inline]
+            fn add_with(&self, other: &Self, arithmetic: Arithmetic) -> Option<Self> {
+                match arithmetic {
+                    Arithmetic::Checked => self.checked_add(*other),
+                    Arithmetic::Wrapping => Some(self.wrapping_add(*other)),
+                    Arithmetic::Saturating => Some(self.saturating_add(*other)),
+                }
+            }
+
+            #[inline]
+            fn mul_with(&self, other: &Self, arithmetic: Arithmetic) -> Option<Self> {
+                match arithmetic {
+                    Arithmetic::Checked => self.checked_mul(*other),
+                    Arithmetic::Wrapping => Some(self.wrapping_mul(*other)),
+                    Arithmetic::Saturating => Some(self.saturating_mul(*other)),
+                }
+            }
+        }
+    };
+}
+
+primitive_word!(i64);
+primitive_word!(i128);
+
+// Never overflows, so every arithmetic policy gives the same answer
+impl Word for BigInt {
+    fn from_i64(v: i64) -> Self {
+        BigInt::from(v)
+    }
+
+    fn to_i64(&self) -> Option<i64> {
+        i64::try_from(self).ok()
+    }
+
+    fn add_with(&self, other: &Self, _: Arithmetic) -> Option<Self> {
+        Some(self + other)
+    }
+
+    fn mul_with(&self, other: &Self, _: Arithmetic) -> Option<Self> {
+        Some(self * other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{assemble, IntcodeError, IntcodeErrorKind, IntcodeVM, PagedMemory};
+
+    // Squares 3 as many times as asked, printing every square
+    const SQUARES: &str = "
+            in [n]
+        loop:
+            mul [x], [x], [x]
+            out [x]
+            add [n], #-1, [n]
+            jt [n], #loop
+            hlt
+        x:  data 3
+        n:  data 0
+    ";
+
+    fn squares<W: Word>(times: i64, cached: bool) -> Result<Vec<W>, IntcodeError<W>> {
+        let program: Vec<W> = assemble("squares", SQUARES)
+            .unwrap()
+            .into_iter()
+            .map(W::from_i64)
+            .collect();
+        let mut vm = IntcodeVM::new(PagedMemory::from(program));
+        vm.set_decode_cache(cached);
+        vm.run_with_inputs(vec![W::from_i64(times)])
+    }
+
+    #[test]
+    fn test_wider_words() {
+        assert_eq!(
+            squares::<i64>(5, false).unwrap().last(),
+            Some(&3i64.pow(32))
+        );
+        assert_eq!(
+            squares::<i64>(6, false).unwrap_err().kind,
+            IntcodeErrorKind::Overflow
+        );
+
+        for &cached in &[false, true] {
+            let outputs = squares::<i128>(6, cached).unwrap();
+            assert_eq!(outputs.last(), Some(&3i128.pow(64)));
+            assert_eq!(
+                squares::<i128>(7, cached).unwrap_err().kind,
+                IntcodeErrorKind::Overflow
+            );
+
+            let outputs = squares::<BigInt>(10, cached).unwrap();
+            assert_eq!(outputs.last(), Some(&BigInt::from(3).pow(1024)));
+        }
+    }
+}
@@ -0,0 +1,5 @@
+//! The parts of the repo that aren't a particular day's solution: input parsing and the Intcode
+//! VM. They live in a library so benchmarks can link against them.
+pub mod consume;
+pub mod futil;
+pub mod intcode;
@@ -1,11 +1,9 @@
 extern crate anyhow;
 extern crate structopt;
 
+use aoc::{consume, futil, intcode};
 use std::path::PathBuf;
 use structopt::*;
-mod consume;
-mod futil;
-mod intcode;
 mod y2019p1;
 mod y2019p2;
 mod y2019p3;
@@ -64,11 +62,102 @@ enum Y2021 {
     P4 { input: PathBuf },
 }
 
+#[derive(StructOpt)]
+enum IntcodeTool {
+    /// Print a listing of an Intcode program
+    Disasm { input: PathBuf },
+    /// Assemble a listing into the comma-separated format puzzle inputs use
+    Asm { input: PathBuf },
+    /// Draw an Intcode program's control flow graph in Graphviz DOT
+    Cfg {
+        input: PathBuf,
+        /// Write the graph to this file instead of printing it
+        #[structopt(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Decompile an Intcode program into C-like pseudocode
+    Decompile { input: PathBuf },
+    /// Transpile an Intcode program into a Rust module
+    Transpile {
+        input: PathBuf,
+        /// Write the module to this file instead of printing it
+        #[structopt(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Step through an Intcode program interactively
+    Debug { input: PathBuf },
+    /// Run an Intcode program, tracing every instruction it executes
+    Trace {
+        input: PathBuf,
+        /// Values to feed the program as input
+        #[structopt(short, long)]
+        feed: Vec<i64>,
+        /// Write the whole trace to this file instead of printing the end of it
+        #[structopt(short, long)]
+        output: Option<PathBuf>,
+        /// How many of the last instructions executed to print
+        #[structopt(long, default_value = "50")]
+        last: usize,
+        /// What add and mul do on overflow: checked, wrapping or saturating
+        #[structopt(long, default_value = "checked")]
+        arithmetic: intcode::Arithmetic,
+        /// Stop after executing this many instructions
+        #[structopt(long)]
+        fuel: Option<usize>,
+        /// Stop if the program gets stuck going round the same loop forever
+        #[structopt(long)]
+        detect_loops: bool,
+    },
+    /// Run an Intcode program and report where it spends its time
+    Profile {
+        input: PathBuf,
+        /// Values to feed the program as input
+        #[structopt(short, long)]
+        feed: Vec<i64>,
+        /// Write folded stacks for a flame graph to this file
+        #[structopt(long)]
+        folded: Option<PathBuf>,
+        /// How many of the hottest entries to show in each list
+        #[structopt(long, default_value = "10")]
+        top: usize,
+        /// What add and mul do on overflow: checked, wrapping or saturating
+        #[structopt(long, default_value = "checked")]
+        arithmetic: intcode::Arithmetic,
+    },
+    /// Run copies of an Intcode program with each one's output feeding the next
+    Chain {
+        input: PathBuf,
+        /// The first input for each copy, one copy per value
+        #[structopt(short, long, required = true)]
+        setup: Vec<i64>,
+        /// Values to feed the first copy after its setup value
+        #[structopt(short, long)]
+        feed: Vec<i64>,
+        /// Feed the last copy's output back into the first
+        #[structopt(long)]
+        feedback: bool,
+    },
+    /// Time an Intcode program with and without the decode cache
+    Bench {
+        input: PathBuf,
+        /// Values to feed the program as input
+        #[structopt(short, long)]
+        feed: Vec<i64>,
+        /// How many times to run the program each way
+        #[structopt(long, default_value = "100")]
+        runs: u32,
+        /// What add and mul do on overflow: checked, wrapping or saturating
+        #[structopt(long, default_value = "checked")]
+        arithmetic: intcode::Arithmetic,
+    },
+}
+
 #[derive(StructOpt)]
 enum Year {
     Y2019(Y2019),
     Y2020(Y2020),
     Y2021(Y2021),
+    Intcode(IntcodeTool),
 }
 
 fn run2019(y: &Y2019) -> Result<(), anyhow::Error> {
@@ -152,12 +241,81 @@ fn run2021(y: &Y2021) -> Result<(), anyhow::Error> {
     Ok(())
 }
 
+fn run_intcode(t: &IntcodeTool) -> Result<(), anyhow::Error> {
+    match t {
+        IntcodeTool::Disasm { input } => {
+            intcode::tools::disasm(input)?;
+        }
+        IntcodeTool::Asm { input } => {
+            intcode::tools::asm(input)?;
+        }
+        IntcodeTool::Cfg { input, output } => {
+            intcode::tools::cfg(input, output.as_deref())?;
+        }
+        IntcodeTool::Decompile { input } => {
+            intcode::tools::decompile(input)?;
+        }
+        IntcodeTool::Transpile { input, output } => {
+            intcode::tools::transpile(input, output.as_deref())?;
+        }
+        IntcodeTool::Debug { input } => {
+            intcode::tools::debug(input)?;
+        }
+        IntcodeTool::Trace {
+            input,
+            feed,
+            output,
+            last,
+            arithmetic,
+            fuel,
+            detect_loops,
+        } => {
+            intcode::tools::trace(
+                input,
+                feed,
+                output.as_deref(),
+                *last,
+                *arithmetic,
+                *fuel,
+                *detect_loops,
+            )?;
+        }
+        IntcodeTool::Profile {
+            input,
+            feed,
+            folded,
+            top,
+            arithmetic,
+        } => {
+            intcode::tools::profile(input, feed, folded.as_deref(), *top, *arithmetic)?;
+        }
+        IntcodeTool::Chain {
+            input,
+            setup,
+            feed,
+            feedback,
+        } => {
+            intcode::tools::chain(input, setup, feed, *feedback)?;
+        }
+        IntcodeTool::Bench {
+            input,
+            feed,
+            runs,
+            arithmetic,
+        } => {
+            intcode::tools::bench(input, feed, *runs, *arithmetic)?;
+        }
+    }
+    Ok(())
+}
+
 fn main() {
     let opt = Year::from_args();
     let r = match opt {
         Year::Y2019(y) => run2019(&y),
         Year::Y2020(y) => run2020(&y),
         Year::Y2021(y) => run2021(&y),
+        Year::Intcode(t) => run_intcode(&t),
     };
 
     if let Err(err) = r {
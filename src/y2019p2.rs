@@ -1,35 +1,49 @@
 use std::path::PathBuf;
 
-use anyhow::Context;
-
-use crate::intcode::IntcodeVM;
-
-fn run_variation(data: &Vec<i64>, noun: i64, verb: i64) -> Result<i64, anyhow::Error> {
-    let mut running_data = data.clone();
-
-    running_data[1] = noun;
-    running_data[2] = verb;
+use anyhow::{anyhow, Context};
+
+use crate::intcode::{IntcodeMemory, IntcodeVM, PagedMemory};
+
+// Every variation forks the same VM, so each one only copies the pages of the program it writes to
+fn run_variation(
+    base: &IntcodeVM<PagedMemory>,
+    noun: i64,
+    verb: i64,
+) -> Result<i64, anyhow::Error> {
+    let mut vm = base.fork();
+
+    for &(address, value) in &[(1, noun), (2, verb)] {
+        match vm.data_mut().load_mut(address) {
+            Some(cell) => *cell = value,
+            None => return Err(anyhow!("Address {} is out of bounds", address)),
+        }
+    }
 
-    let mut vm = IntcodeVM::new(running_data);
     vm.run().with_context(|| {
         format!(
             "Failed to execute program with noun: {} verb: {}",
             noun, verb
         )
     })?;
-    return Ok(vm.data()[0]);
+    vm.data()
+        .load(0)
+        .ok_or_else(|| anyhow!("Address 0 is out of bounds"))
 }
 
 pub fn y2019p2(input: &PathBuf) -> Result<(), anyhow::Error> {
     let intcode_data =
         crate::futil::read_csints(input).with_context(|| "Failed to read input program")?;
+    if intcode_data.len() < 3 {
+        return Err(anyhow!("Program is too short to take a noun and verb"));
+    }
+    let base = IntcodeVM::new(PagedMemory::from(intcode_data));
 
-    let result = run_variation(&intcode_data, 12, 2)?;
+    let result = run_variation(&base, 12, 2)?;
     println!("Output: {}", result);
 
     for noun in 0..99 {
         for verb in 0..99 {
-            let result = run_variation(&intcode_data, noun, verb)?;
+            let result = run_variation(&base, noun, verb)?;
             if result == 19690720 {
                 println!("Found answer: {}, {} ({})", noun, verb, 100 * noun + verb);
             }
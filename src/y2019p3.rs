@@ -1,4 +1,4 @@
-use anyhow::anyhow;
+use crate::consume::{parse_all, ParseResult, Scanner};
 use std::cmp;
 use std::collections::HashSet;
 use std::path::PathBuf;
@@ -142,6 +142,7 @@ struct PathBuilder<'a> {
     path: &'a mut Path,
 }
 
+#[derive(Clone, Copy)]
 enum Motion {
     Up,
     Down,
@@ -170,31 +171,26 @@ impl<'a> PathBuilder<'a> {
     }
 }
 
-fn parse_motion(m: &str) -> Result<(Motion, i32), anyhow::Error> {
-    let mut chars = m.chars();
-    let motion = match chars.next() {
-        Some(c) => match c {
-            'U' => Motion::Up,
-            'D' => Motion::Down,
-            'L' => Motion::Left,
-            'R' => Motion::Right,
-            c => return Err(anyhow!("Invalid motion: {}", c)),
-        },
-        None => return Err(anyhow!("No motion character")),
-    };
-
-    let magnitude = chars.as_str().parse::<u32>()? as i32;
+fn parse_motion(s: &mut Scanner) -> ParseResult<(Motion, i32)> {
+    s.whitespace();
+    let motion = s.keyword(&[
+        ("U", Motion::Up),
+        ("D", Motion::Down),
+        ("L", Motion::Left),
+        ("R", Motion::Right),
+    ])?;
+    let magnitude = s.int::<u32>()? as i32;
+    s.whitespace();
 
     Ok((motion, magnitude))
 }
 
-fn build_path_from_str(s: &str) -> Result<Path, anyhow::Error> {
-    let motions = s.split(",").map(|point| point.trim()).map(parse_motion);
+fn build_path_from_str(s: &str) -> ParseResult<Path> {
+    let motions = parse_all(s, |s| s.separated(",", parse_motion))?;
     let mut path = Path::new();
     let mut builder = PathBuilder::new(&mut path);
 
-    for m in motions {
-        let (motion, magnitude) = m?;
+    for (motion, magnitude) in motions {
         builder.append(motion, magnitude);
     }
 
@@ -202,13 +198,7 @@ fn build_path_from_str(s: &str) -> Result<Path, anyhow::Error> {
 }
 
 pub fn y2019p3(input: &PathBuf) -> Result<(), anyhow::Error> {
-    let path_strings = crate::futil::read_lines(input)?;
-    let mut paths = Vec::new();
-    for path_string_maybe in path_strings {
-        let path_string = path_string_maybe?;
-
-        paths.push(build_path_from_str(&path_string)?);
-    }
+    let paths = crate::futil::parse_lines_with(input, build_path_from_str)?;
 
     let mut intersections = HashSet::new();
     let mut fewest_combined_steps = i32::MAX;
@@ -1,8 +1,7 @@
-use crate::futil::read_lines;
+use crate::futil::parse_lines;
+use proc::ParseLine;
 use std::path::PathBuf;
 
-use regex::Regex;
-
 #[derive(Clone, Copy)]
 enum Direction {
     North,
@@ -68,8 +67,26 @@ impl Coord {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, ParseLine)]
+enum Action {
+    #[pattern("N{0}")]
+    North(i32),
+    #[pattern("S{0}")]
+    South(i32),
+    #[pattern("E{0}")]
+    East(i32),
+    #[pattern("W{0}")]
+    West(i32),
+    #[pattern("L{0}")]
+    Left(i32),
+    #[pattern("R{0}")]
+    Right(i32),
+    #[pattern("F{0}")]
+    Forward(i32),
+}
+
 pub fn y2020p12(input: &PathBuf) -> Result<(), anyhow::Error> {
-    let re = Regex::new("(\\w)(\\d+)")?;
+    let actions: Vec<Action> = parse_lines(input)?;
 
     let mut ship = Coord {
         x: 0,
@@ -77,24 +94,15 @@ pub fn y2020p12(input: &PathBuf) -> Result<(), anyhow::Error> {
         orientation: Direction::East,
     };
 
-    for maybe_line in read_lines(input)? {
-        let line = maybe_line?;
-
-        let caps = re.captures(&line).unwrap();
-
-        let c0 = caps.get(1).unwrap().as_str();
-        let c1 = caps.get(2).unwrap().as_str();
-
-        let a = c1.parse::<i32>()?;
-        match c0 {
-            "N" => ship.apply(Direction::North, a),
-            "S" => ship.apply(Direction::South, a),
-            "E" => ship.apply(Direction::East, a),
-            "W" => ship.apply(Direction::West, a),
-            "L" => ship.orientation = ship.orientation.with_rotation(false, a),
-            "R" => ship.orientation = ship.orientation.with_rotation(true, a),
-            "F" => ship.apply(ship.orientation, a),
-            _ => panic!("FUCK"),
+    for action in &actions {
+        match *action {
+            Action::North(a) => ship.apply(Direction::North, a),
+            Action::South(a) => ship.apply(Direction::South, a),
+            Action::East(a) => ship.apply(Direction::East, a),
+            Action::West(a) => ship.apply(Direction::West, a),
+            Action::Left(a) => ship.orientation = ship.orientation.with_rotation(false, a),
+            Action::Right(a) => ship.orientation = ship.orientation.with_rotation(true, a),
+            Action::Forward(a) => ship.apply(ship.orientation, a),
         };
     }
 
@@ -111,28 +119,19 @@ pub fn y2020p12(input: &PathBuf) -> Result<(), anyhow::Error> {
         orientation: Direction::East,
     };
 
-    for maybe_line in read_lines(input)? {
-        let line = maybe_line?;
-
-        let caps = re.captures(&line).unwrap();
-
-        let c0 = caps.get(1).unwrap().as_str();
-        let c1 = caps.get(2).unwrap().as_str();
-
-        let a = c1.parse::<i32>()?;
-        match c0 {
-            "N" => waypoint.apply(Direction::North, a),
-            "S" => waypoint.apply(Direction::South, a),
-            "E" => waypoint.apply(Direction::East, a),
-            "W" => waypoint.apply(Direction::West, a),
-            "L" => waypoint.rotate_about_zero(false, a),
-            "R" => waypoint.rotate_about_zero(true, a),
-            "F" => {
+    for action in &actions {
+        match *action {
+            Action::North(a) => waypoint.apply(Direction::North, a),
+            Action::South(a) => waypoint.apply(Direction::South, a),
+            Action::East(a) => waypoint.apply(Direction::East, a),
+            Action::West(a) => waypoint.apply(Direction::West, a),
+            Action::Left(a) => waypoint.rotate_about_zero(false, a),
+            Action::Right(a) => waypoint.rotate_about_zero(true, a),
+            Action::Forward(a) => {
                 for _ in 0..a {
                     ship.waypoint_move(&waypoint)
                 }
             }
-            _ => panic!("FUCK"),
         };
     }
     println!("{} {} {}", ship.x, ship.y, ship.x.abs() + ship.y.abs());
@@ -144,5 +143,16 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test() {}
+    fn test() {
+        assert_eq!("F10".parse::<Action>().unwrap(), Action::Forward(10));
+        assert_eq!("R90".parse::<Action>().unwrap(), Action::Right(90));
+
+        let err = "R9O".parse::<Action>().unwrap_err();
+        assert_eq!(err.offset, 1);
+        assert_eq!(
+            err.expected,
+            "Action::Right.0 (invalid digit found in string: \"9O\")"
+        );
+        assert_eq!("X90".parse::<Action>().unwrap_err().offset, 0);
+    }
 }
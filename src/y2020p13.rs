@@ -1,4 +1,4 @@
-use crate::futil::read_lines;
+use crate::futil::{ints, read_input};
 use std::path::PathBuf;
 
 fn fuck(d: u64, i: &Vec<(usize, u64)>) -> bool {
@@ -12,16 +12,13 @@ fn fuck(d: u64, i: &Vec<(usize, u64)>) -> bool {
 }
 
 pub fn y2020p13(input: &PathBuf) -> Result<(), anyhow::Error> {
-    let mut lines = read_lines(input)?;
+    let file = read_input(input)?;
+    let mut sections = file.sections();
+    let arrival = sections.line("arrival time")?.text.trim().parse::<u64>()?;
+    let schedule = sections.line("bus schedule")?.text.trim();
+    sections.end()?;
 
-    let arrival = lines.next().unwrap()?.parse::<u64>()?;
-    let busses: Vec<u64> = lines
-        .next()
-        .unwrap()?
-        .split(",")
-        .filter(|x| *x != "x")
-        .map(|i| i.parse::<u64>().unwrap())
-        .collect();
+    let busses = ints::<u64>(schedule)?;
 
     let mut m: Vec<(u64, u64)> = busses
         .iter()
@@ -37,12 +34,7 @@ pub fn y2020p13(input: &PathBuf) -> Result<(), anyhow::Error> {
 
     println!("{:?}", m);
 
-    let mut lines = read_lines(input)?;
-
-    let _ = lines.next().unwrap()?.parse::<u64>()?;
-    let busses: Vec<(usize, u64)> = lines
-        .next()
-        .unwrap()?
+    let busses: Vec<(usize, u64)> = schedule
         .split(",")
         .enumerate()
         .filter(|(_, x)| *x != "x")
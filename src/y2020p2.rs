@@ -1,34 +1,28 @@
-extern crate regex;
-
-use crate::futil::read_lines;
-use anyhow::anyhow;
-use regex::Regex;
+use crate::futil::parse_lines;
+use proc::ParseLine;
 use std::path::PathBuf;
 
+#[derive(ParseLine)]
+#[pattern("{min}-{max} {ch}: {password}")]
+struct Policy {
+    min: usize,
+    max: usize,
+    ch: char,
+    password: String,
+}
+
 pub fn y2020p2(input: &PathBuf) -> Result<(), anyhow::Error> {
-    let re = Regex::new("(\\d+)-(\\d+) (\\w): (\\w+)")?;
     let mut passes1 = 0;
     let mut passes2 = 0;
-    for maybe_line in read_lines(input)? {
-        let line = maybe_line?;
-
-        let captures = re
-            .captures(&line)
-            .ok_or(anyhow!("line did not match expected syntax"))?;
-
-        // This is all safe and won't panic because of the fixed regex above
-        let a = captures.get(1).unwrap().as_str().parse::<usize>()?;
-        let b = captures.get(2).unwrap().as_str().parse::<usize>()?;
-        let ch = captures.get(3).unwrap().as_str().chars().next().unwrap();
-        let p = captures.get(4).unwrap().as_str();
+    for policy in parse_lines::<_, Policy>(input)? {
         let mut i = 0;
         let mut j = 0;
 
-        for (z, c) in p.chars().enumerate() {
-            if c == ch {
+        for (z, c) in policy.password.chars().enumerate() {
+            if c == policy.ch {
                 i += 1;
                 let z1 = z + 1;
-                if z1 == a || z1 == b {
+                if z1 == policy.min || z1 == policy.max {
                     j += 1;
                 }
             }
@@ -38,7 +32,7 @@ pub fn y2020p2(input: &PathBuf) -> Result<(), anyhow::Error> {
             passes2 += 1;
         }
 
-        if a <= i && i <= b {
+        if policy.min <= i && i <= policy.max {
             passes1 += 1;
         }
     }
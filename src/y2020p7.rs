@@ -1,52 +1,51 @@
-use crate::futil::read_lines;
+use crate::consume::{parse_all, ParseResult, Scanner};
+use crate::futil::parse_lines_with;
 use std::path::PathBuf;
 
 use std::collections::HashMap;
 
-#[derive(Eq, Hash, PartialEq)]
+#[derive(Debug, Eq, Hash, PartialEq)]
 struct BagIdentifier {
     attr: String,
     color: String,
 }
 
 impl BagIdentifier {
-    fn take_ident<'a, T: Iterator<Item = &'a str>>(i: &mut T) -> Option<BagIdentifier> {
-        i.next().and_then(|attr| {
-            i.next().map(|color| BagIdentifier {
-                attr: attr.to_owned(),
-                color: color.to_owned(),
-            })
+    fn parse(s: &mut Scanner) -> ParseResult<BagIdentifier> {
+        let attr = s.ident()?;
+        s.literal(" ")?;
+        let color = s.ident()?;
+        Ok(BagIdentifier {
+            attr: attr.to_owned(),
+            color: color.to_owned(),
         })
     }
 }
 
 type BagRegistry = HashMap<BagIdentifier, BagDefinition>;
 
+#[derive(Debug)]
 struct BagDefinition {
     contents: Vec<(u32, BagIdentifier)>,
 }
 
 impl BagDefinition {
-    fn take_bag_contents<'a, T: Iterator<Item = &'a str>>(
-        i: &mut T,
-    ) -> Option<(u32, BagIdentifier)> {
-        i.next()
-            .and_then(|quantity| quantity.parse::<u32>().ok())
-            .zip(BagIdentifier::take_ident(i))
-            // take 'bags'
-            .zip(i.next())
-            .map(|(v, _)| v)
+    fn parse_bag_contents(s: &mut Scanner) -> ParseResult<(u32, BagIdentifier)> {
+        let quantity = s.int::<u32>()?;
+        s.literal(" ")?;
+        let id = BagIdentifier::parse(s)?;
+        s.literal(" bag")?;
+        s.optional(|s| s.literal("s"));
+        Ok((quantity, id))
     }
 
-    fn take_contents<'a, T: Iterator<Item = &'a str>>(i: &mut T) -> BagDefinition {
-        let mut bd = BagDefinition {
-            contents: Vec::new(),
+    fn parse(s: &mut Scanner) -> ParseResult<BagDefinition> {
+        let contents = match s.optional(|s| s.literal("no other bags")) {
+            Some(_) => Vec::new(),
+            None => s.separated(", ", BagDefinition::parse_bag_contents)?,
         };
-
-        while let Some(bc) = BagDefinition::take_bag_contents(i) {
-            bd.contents.push(bc);
-        }
-        bd
+        s.literal(".")?;
+        Ok(BagDefinition { contents })
     }
 
     fn contains_bag(&self, bid: &BagIdentifier, br: &BagRegistry) -> bool {
@@ -78,15 +77,14 @@ impl BagDefinition {
 pub fn y2020p7(input: &PathBuf) -> Result<(), anyhow::Error> {
     let mut registry = HashMap::<BagIdentifier, BagDefinition>::new();
 
-    for maybe_line in read_lines(input)? {
-        let line = maybe_line?;
-        let mut parts = line.split(" ");
-
-        let id = BagIdentifier::take_ident(&mut parts).unwrap();
-        parts.next(); // bags
-        parts.next(); // contain
-        let contents = BagDefinition::take_contents(&mut parts);
-
+    let rules = parse_lines_with(input, |line| {
+        parse_all(line, |s| {
+            let id = BagIdentifier::parse(s)?;
+            s.literal(" bags contain ")?;
+            Ok((id, BagDefinition::parse(s)?))
+        })
+    })?;
+    for (id, contents) in rules {
         registry.insert(id, contents);
     }
 
@@ -108,4 +106,22 @@ pub fn y2020p7(input: &PathBuf) -> Result<(), anyhow::Error> {
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rule() {
+        let mut s = Scanner::new("1 bright white bag, 2 muted yellow bags.");
+        let def = BagDefinition::parse(&mut s).unwrap();
+        assert_eq!(def.contents.len(), 2);
+        assert_eq!(def.contents[1].0, 2);
+        assert_eq!(def.contents[1].1.attr, "muted");
+        assert_eq!(def.contents[1].1.color, "yellow");
+
+        let mut s = Scanner::new("no other bags.");
+        assert!(BagDefinition::parse(&mut s).unwrap().contents.is_empty());
+
+        let mut s = Scanner::new("1 bright white bag; 2 muted yellow bags.");
+        assert_eq!(BagDefinition::parse(&mut s).unwrap_err().offset, 18);
+    }
+}
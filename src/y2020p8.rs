@@ -1,15 +1,10 @@
-use crate::futil::read_lines;
-use anyhow::anyhow;
+use crate::futil::parse_lines;
+use proc::ParseLine;
 use std::collections::HashSet;
 use std::path::PathBuf;
 
 pub fn y2020p8(input: &PathBuf) -> Result<(), anyhow::Error> {
-    let mut instructions = Vec::new();
-    for maybe_line in read_lines(input)? {
-        let line = maybe_line?;
-        let instruction = Instruction::from_line(&line)?;
-        instructions.push(instruction);
-    }
+    let instructions: Vec<Instruction> = parse_lines(input)?;
 
     let mut vm = VM::new(&instructions);
 
@@ -47,29 +42,16 @@ pub fn y2020p8(input: &PathBuf) -> Result<(), anyhow::Error> {
     Ok(())
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, ParseLine)]
 enum Instruction {
+    #[pattern("acc {0}")]
     Accumulator(i32),
+    #[pattern("jmp {0}")]
     Jump(i32),
+    #[pattern("nop {0}")]
     Nop(i32),
 }
 
-impl Instruction {
-    fn from_line(s: &str) -> Result<Instruction, anyhow::Error> {
-        let mut args = s.split(" ");
-
-        let op = args.next().unwrap();
-        let arg = args.next().unwrap();
-
-        match op {
-            "nop" => Ok(Instruction::Nop(arg.parse::<i32>()?)),
-            "acc" => Ok(Instruction::Accumulator(arg.parse::<i32>()?)),
-            "jmp" => Ok(Instruction::Jump(arg.parse::<i32>()?)),
-            _ => Err(anyhow!("Not a valid op: {}", op)),
-        }
-    }
-}
-
 struct VM<'a> {
     accum: i32,
     ip: i32,
@@ -1,15 +1,10 @@
-use crate::futil::read_lines;
+use crate::consume::parse_all;
+use crate::futil::parse_lines_with;
 use anyhow::Result;
 use std::path::PathBuf;
 
 pub fn y2021p1(input: &PathBuf) -> Result<(), anyhow::Error> {
-    let nums: Vec<i64> = read_lines(input)?
-        .map(|maybe_line| {
-            maybe_line
-                .map_err(anyhow::Error::from)
-                .and_then(|l| l.parse::<i64>().map_err(anyhow::Error::from))
-        })
-        .collect::<Result<Vec<i64>, anyhow::Error>>()?;
+    let nums: Vec<i64> = parse_lines_with(input, |line| parse_all(line, |s| s.int()))?;
 
     let mut i = 0;
     let mut depths = nums.iter();
@@ -1,7 +1,6 @@
-use crate::futil::read_lines;
-use anyhow::{anyhow, Context};
+use crate::futil::parse_lines;
+use proc::ParseLine;
 use std::path::PathBuf;
-use std::str::FromStr;
 
 struct SubCoords {
     h: i64,
@@ -9,32 +8,16 @@ struct SubCoords {
     a: i64,
 }
 
+#[derive(ParseLine)]
 enum Command {
+    #[pattern("forward {0}")]
     Forward(i64),
+    #[pattern("down {0}")]
     Down(i64),
+    #[pattern("up {0}")]
     Up(i64),
 }
 
-impl FromStr for Command {
-    type Err = anyhow::Error;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut s_parts = s.split(" ");
-        let cmd = s_parts.next().ok_or(anyhow!("missing command"))?;
-        let v = s_parts
-            .next()
-            .ok_or(anyhow!("missing distance"))?
-            .parse::<i64>()
-            .context("malformed distance")?;
-        match cmd {
-            "forward" => Ok(Command::Forward(v)),
-            "down" => Ok(Command::Down(v)),
-            "up" => Ok(Command::Up(v)),
-            _ => Err(anyhow!("unexpected command")),
-        }
-    }
-}
-
 impl Command {
     fn apply_to_sub(&self, sub: &mut SubCoords) {
         match self {
@@ -57,11 +40,10 @@ impl Command {
 }
 
 pub fn y2021p2(input: &PathBuf) -> Result<(), anyhow::Error> {
-    let cmds = read_lines(input)?;
+    let cmds: Vec<Command> = parse_lines(input)?;
     let mut sub = SubCoords { h: 0, d: 0, a: 0 };
     let mut sub2 = SubCoords { h: 0, d: 0, a: 0 };
-    for maybe_cmd in cmds {
-        let cmd = Command::from_str(&maybe_cmd?)?;
+    for cmd in cmds {
         cmd.apply_to_sub(&mut sub);
         cmd.apply_to_sub2(&mut sub2);
     }
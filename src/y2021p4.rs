@@ -1,4 +1,5 @@
-use crate::futil::read_lines;
+use crate::futil::read_input;
+use anyhow::anyhow;
 use std::cell::RefCell;
 use std::path::PathBuf;
 
@@ -44,38 +45,24 @@ fn vertical_win(col: u32) -> u32 {
 }
 
 pub fn y2021p4(input: &PathBuf) -> Result<(), anyhow::Error> {
-    let mut lines = read_lines(input)?;
-    let called_numbers = lines
-        .next()
-        .unwrap()?
-        .split(",")
-        .map(|v| v.parse().unwrap())
-        .collect::<Vec<u32>>();
+    let file = read_input(input)?;
+    let mut sections = file.sections();
+    let called_numbers = sections.line("called numbers")?.ints::<u32>()?;
 
     let mut boards = vec![];
+    for block in sections.blocks("boards")? {
+        let spots = block.ints::<u32>()?;
+        if spots.len() != 25 {
+            return Err(anyhow!("{} has {} numbers, not 25", block, spots.len()));
+        }
 
-    // Ensures there is a 'blank line header' for a new board
-    while !lines.next().is_none() {
-        let mut board = Board {
-            spots: vec![],
+        boards.push(Board {
+            spots,
             state: RefCell::new(BoardState {
                 called: 0,
                 has_won: false,
             }),
-        };
-
-        for _ in 0..5 {
-            let l = lines.next().unwrap()?;
-            for entry in l.split(" ") {
-                if entry.is_empty() {
-                    continue;
-                }
-                let val = entry.parse().unwrap();
-                board.spots.push(val);
-            }
-        }
-
-        boards.push(board);
+        });
     }
 
     let mut markers = vec![vec! {}; (*called_numbers.iter().max().unwrap() as usize) + 1];